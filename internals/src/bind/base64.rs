@@ -52,11 +52,21 @@ fn calc_max_input_len(max_output_len: usize) -> usize {
 pub fn encoded_word_encode<O, R: AsRef<str>>( input: R, out: &mut O )
     where O: EncodedWordWriter
 {
-    _encoded_word_encode(input.as_ref(), out)
+    _encoded_word_encode(input.as_ref().as_bytes(), is_utf8_continuation_byte, out)
 }
 
-fn _encoded_word_encode<O>( input: &str, out: &mut O )
+/// encodes the bytes of text in a single byte charset (e.g. `ISO-8859-1`)
+///
+/// Unlike `encoded_word_encode` the input can be split
+/// into multiple encoded words at any byte.
+pub fn encoded_word_encode_single_byte<O, R: AsRef<[u8]>>( input: R, out: &mut O )
     where O: EncodedWordWriter
+{
+    _encoded_word_encode(input.as_ref(), |_| false, out)
+}
+
+fn _encoded_word_encode<O, F>( input: &[u8], is_continuation_byte: F, out: &mut O )
+    where O: EncodedWordWriter, F: Fn(u8) -> bool
 {
     let config = extern_base64::Config::new(
         CHARSET, USE_PADDING, ECW_STRIP_WHITESPACE, NO_LINE_WRAP
@@ -80,12 +90,11 @@ fn _encoded_word_encode<O>( input: &str, out: &mut O )
             rest_len
         } else {
             let mut tmp_split = max_input_len;
-            let rest_bytes = rest.as_bytes();
 
             // the byte at the current index starts with that we are in a
             // position where we can't split and have to move left until
             // the beginning of the utf8
-            while is_utf8_continuation_byte(rest_bytes[tmp_split]) {
+            while is_continuation_byte(rest[tmp_split]) {
                 //UNDERFLOW_SAFE: if the string is correct (contains valid utf8) this cant undeflow as
                 // the first byte cant start with 0b10xxxxxx.
                 tmp_split -= 1;
@@ -1,9 +1,8 @@
 use soft_ascii_string::{ SoftAsciiString, SoftAsciiChar, SoftAsciiStr };
 
 use vec1::Vec1;
-use grammar::encoded_word::{ MAX_ECW_LEN, ECW_SEP_OVERHEAD };
-use ::encoder::EncodingWriter;
-use super::{ EncodedWordWriter, EncodedWordEncoding as Encoding };
+use ::encoder::{EncodingWriter, EncodingWarning};
+use super::{ EncodedWordWriter, EncodedWordEncoding as Encoding, max_payload_len };
 
 pub struct VecWriter<'a> {
     data: Vec1<SoftAsciiString >,
@@ -47,7 +46,7 @@ impl<'a> EncodedWordWriter for VecWriter<'a> {
     }
 
     fn max_payload_len( &self ) -> usize {
-        MAX_ECW_LEN - ECW_SEP_OVERHEAD - self.charset.len() - 1
+        max_payload_len(self.charset.as_str())
     }
 }
 
@@ -93,6 +92,19 @@ impl<'a, 'b: 'a> EncodedWordWriter for WriterWrapper<'a, 'b> {
     }
 
     fn max_payload_len( &self ) -> usize {
-        MAX_ECW_LEN - ECW_SEP_OVERHEAD - self.charset.len() - 1
+        max_payload_len(self.charset.as_str())
+    }
+
+    /// passes a `EncodedWordFallback` warning to the warn sink if a non ascii
+    /// text is encoded for a mail which is not internationalized
+    fn before_encoding( &mut self, text: &str ) {
+        let handle = &mut *self.encoder_handle;
+        if !handle.mail_type().is_internationalized() && !text.is_ascii() {
+            let header = handle.current_header_name().map(|name| name.to_owned());
+            handle.warn(EncodingWarning::EncodedWordFallback {
+                original_len: text.len(),
+                header
+            });
+        }
     }
 }
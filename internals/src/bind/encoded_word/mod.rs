@@ -1,5 +1,8 @@
 use soft_ascii_string::{ SoftAsciiStr, SoftAsciiChar };
 
+use grammar::encoded_word::{ MAX_ECW_LEN, ECW_SEP_OVERHEAD };
+use ::encoder::EncodingWriter;
+use ::error::{EncodingError, EncodingErrorKind};
 use super::{base64, quoted_printable};
 
 mod impls;
@@ -31,11 +34,15 @@ impl EncodedWordEncoding {
     /// As both algorithm need to know about code point boundaries
     /// only encoding utf8 is supported for now
     ///
+    /// Before encoding `out.before_encoding` is called with the input,
+    /// e.g. `WriterWrapper` uses this to emit a `EncodedWordFallback` warning.
+    ///
     pub fn encode<R, O>(&self, input: R, out: &mut O)
         where R: AsRef<str>, O: EncodedWordWriter
     {
         use self::EncodedWordEncoding::*;
         let input: &str = input.as_ref();
+        out.before_encoding(input);
         match *self {
             Base64 => {
                 base64::encoded_word_encode(input, out)
@@ -47,6 +54,78 @@ impl EncodedWordEncoding {
     }
 }
 
+/// The charset the text is converted to before being encoded in a encoded word.
+#[derive(Debug, Copy, Clone, Hash, Eq, PartialEq)]
+pub enum EncodedWordCharset {
+    Utf8, Latin1
+}
+
+impl EncodedWordCharset {
+
+    /// returns the name of the charset used in a encoded word
+    pub fn name(&self) -> &'static SoftAsciiStr {
+        use self::EncodedWordCharset::*;
+        match *self {
+            Utf8 => SoftAsciiStr::from_unchecked("utf8"),
+            Latin1 => SoftAsciiStr::from_unchecked("ISO-8859-1")
+        }
+    }
+}
+
+impl EncodedWordEncoding {
+
+    /// encodes a given utf8 string after converting it to the given charset
+    ///
+    /// Note that `out` has to write the same charset
+    /// (e.g. use `WriterWrapper::new_with_charset` with `charset.name()`).
+    ///
+    /// # Error
+    /// fails with `NotEncodable` if the text contains chars which
+    /// are not part of the charset, in which case nothing is written
+    pub fn encode_with_charset<O>(&self, input: &str, charset: EncodedWordCharset, out: &mut O)
+        -> Result<(), EncodingError>
+        where O: EncodedWordWriter
+    {
+        use self::EncodedWordEncoding::*;
+        match charset {
+            EncodedWordCharset::Utf8 => self.encode(input, out),
+            EncodedWordCharset::Latin1 => {
+                let bytes = latin1_encode(input)?;
+                out.before_encoding(input);
+                match *self {
+                    Base64 => {
+                        base64::encoded_word_encode_single_byte(&bytes, out)
+                    },
+                    QuotedPrintable => {
+                        quoted_printable::encoded_word_encode(bytes.chunks(1), out)
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn latin1_encode(input: &str) -> Result<Vec<u8>, EncodingError> {
+    let mut out = Vec::with_capacity(input.len());
+    for ch in input.chars() {
+        if (ch as u32) < 0x100 {
+            out.push(ch as u32 as u8);
+        } else {
+            return Err(EncodingError::from(EncodingErrorKind::NotEncodable {
+                encoding: "ISO-8859-1"
+            }).with_str_context(input));
+        }
+    }
+    Ok(out)
+}
+
+/// Returns the maximal length of the payload of a encoded word using the given charset.
+fn max_payload_len(charset: &str) -> usize {
+    // the additional 1 is for the `?` between the encoding and the payload
+    MAX_ECW_LEN - ECW_SEP_OVERHEAD - charset.len() - 1
+}
+
 pub trait EncodedWordWriter {
     fn write_char( &mut self, ch: SoftAsciiChar );
     fn write_charset( &mut self );
@@ -61,6 +140,9 @@ pub trait EncodedWordWriter {
     /// able to change what `max_paylod_len` returns.
     fn max_payload_len( &self ) -> usize;
 
+    /// called with the (not yet encoded) text before it is encoded
+    fn before_encoding( &mut self, _text: &str ) {}
+
     fn write_ecw_start( &mut self ) {
         let qm = SoftAsciiChar::from_unchecked('?');
         self.write_char(SoftAsciiChar::from_unchecked('='));
@@ -89,4 +171,251 @@ pub trait EncodedWordWriter {
             self.write_char(ch)
         }
     }
-}
\ No newline at end of file
+}
+
+/// Writes the text as encoded word(s) using the given encoding and charset.
+///
+/// Like when using `WriterWrapper` directly a `EncodingWarning::EncodedWordFallback`
+/// is passed to the warn sink if the mail is not internationalized and the
+/// text is not ascii.
+///
+/// # Error
+/// fails with `NotEncodable` if the text contains chars which
+/// are not part of the charset, in which case nothing is written
+pub fn write_encoded_word(
+    handle: &mut EncodingWriter,
+    text: &str,
+    encoding: EncodedWordEncoding,
+    charset: EncodedWordCharset
+) -> Result<(), EncodingError> {
+    let mut writer = WriterWrapper::new_with_charset(charset.name(), encoding, handle);
+    encoding.encode_with_charset(text, charset, &mut writer)
+}
+
+/// Returns the number of encoded words the text is split into when encoded with the given encoding and charset.
+///
+/// This uses the same splitting logic as `EncodedWordEncoding::encode_with_charset`.
+///
+/// # Error
+/// fails with `NotEncodable` if the text contains chars which
+/// are not part of the charset
+pub fn encoded_word_chunk_count(
+    text: &str,
+    encoding: EncodedWordEncoding,
+    charset: EncodedWordCharset
+) -> Result<usize, EncodingError> {
+    let mut counter = ChunkCounter { encoding, charset, chunks: 1 };
+    encoding.encode_with_charset(text, charset, &mut counter)?;
+    Ok(counter.chunks)
+}
+
+/// `EncodedWordWriter` which only counts the encoded words written to it
+struct ChunkCounter {
+    encoding: EncodedWordEncoding,
+    charset: EncodedWordCharset,
+    chunks: usize
+}
+
+impl EncodedWordWriter for ChunkCounter {
+
+    fn write_char( &mut self, _ch: SoftAsciiChar ) {}
+
+    fn write_charset( &mut self ) {}
+
+    fn encoding( &self ) -> EncodedWordEncoding {
+        self.encoding
+    }
+
+    fn write_ecw_seperator( &mut self ) {
+        self.chunks += 1;
+    }
+
+    fn max_payload_len( &self ) -> usize {
+        max_payload_len(self.charset.name().as_str())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use soft_ascii_string::SoftAsciiString;
+    use ::encoder::EncodingWarning;
+    use super::*;
+
+    #[test]
+    fn short_text_is_one_chunk() {
+        let utf8 = EncodedWordCharset::Utf8;
+        assert_eq!(assert_ok!(encoded_word_chunk_count("hy ho", EncodedWordEncoding::Base64, utf8)), 1);
+        assert_eq!(
+            assert_ok!(encoded_word_chunk_count("hy ho", EncodedWordEncoding::QuotedPrintable, utf8)),
+            1
+        );
+    }
+
+    #[test]
+    fn long_multi_byte_text_is_multiple_chunks() {
+        let text: String = ::std::iter::repeat('ü').take(40).collect();
+        let utf8 = EncodedWordCharset::Utf8;
+        // 80 bytes, at most 48 input bytes per encoded word
+        assert_eq!(assert_ok!(encoded_word_chunk_count(&text, EncodedWordEncoding::Base64, utf8)), 2);
+        // each 'ü' is encoded as "=C3=BC", at most 10 fit into one encoded word
+        assert_eq!(
+            assert_ok!(encoded_word_chunk_count(&text, EncodedWordEncoding::QuotedPrintable, utf8)),
+            4
+        );
+    }
+
+    #[test]
+    fn latin1_chunk_count() {
+        let text: String = ::std::iter::repeat('ü').take(40).collect();
+        let latin1 = EncodedWordCharset::Latin1;
+        // 40 bytes, at most 42 input bytes per encoded word
+        assert_eq!(assert_ok!(encoded_word_chunk_count(&text, EncodedWordEncoding::Base64, latin1)), 1);
+        // each 'ü' is encoded as "=FC", at most 19 fit into one encoded word
+        assert_eq!(
+            assert_ok!(encoded_word_chunk_count(&text, EncodedWordEncoding::QuotedPrintable, latin1)),
+            3
+        );
+
+        let mut out = VecWriter::new(latin1.name(), EncodedWordEncoding::QuotedPrintable);
+        assert_ok!(EncodedWordEncoding::QuotedPrintable.encode_with_charset(&text, latin1, &mut out));
+        assert_eq!(out.data().len(), 3);
+    }
+
+    #[test]
+    fn write_encoded_word_warns_on_fallback() {
+        use std::sync::{Arc, Mutex};
+        use soft_ascii_string::SoftAsciiStr;
+        use ::encoder::EncodingBuffer;
+        use ::MailType;
+
+        let collected = Arc::new(Mutex::new(Vec::new()));
+        let sink_collected = collected.clone();
+        let mut encoder = EncodingBuffer::new(MailType::Ascii);
+        encoder.set_warn_sink(Box::new(move |warning| {
+            sink_collected.lock().unwrap().push(warning)
+        }));
+
+        assert_ok!(encoder.write_header_line(|hdl| {
+            hdl.write_str(SoftAsciiStr::from_unchecked("Subject: "))?;
+            write_encoded_word(
+                hdl, "Grüße", EncodedWordEncoding::QuotedPrintable, EncodedWordCharset::Utf8)
+        }));
+
+        assert_eq!(
+            assert_ok!(encoder.as_str()),
+            "Subject: =?utf8?Q?Gr=C3=BC=C3=9Fe?=\r\n"
+        );
+        assert_eq!(*collected.lock().unwrap(), vec![
+            EncodingWarning::EncodedWordFallback {
+                original_len: 7,
+                header: Some("Subject".to_owned())
+            }
+        ]);
+    }
+
+    #[test]
+    fn encoding_component_warns_on_fallback() {
+        use std::sync::{Arc, Mutex};
+        use soft_ascii_string::SoftAsciiStr;
+        use ::encoder::{EncodableInHeader, EncodingBuffer};
+        use ::MailType;
+
+        /// minimal unstructured component, encoding like the ones in `mail-headers`
+        #[derive(Debug, Clone)]
+        struct Unstructured(&'static str);
+
+        impl EncodableInHeader for Unstructured {
+            fn encode(&self, handle: &mut EncodingWriter) -> Result<(), EncodingError> {
+                if handle.mail_type().is_internationalized() || self.0.is_ascii() {
+                    handle.write_str_unchecked(self.0)
+                } else {
+                    let encoding = EncodedWordEncoding::QuotedPrintable;
+                    let mut writer = WriterWrapper::new(encoding, handle);
+                    encoding.encode(self.0, &mut writer);
+                    Ok(())
+                }
+            }
+
+            fn boxed_clone(&self) -> Box<EncodableInHeader> {
+                Box::new(self.clone())
+            }
+        }
+
+        let collected = Arc::new(Mutex::new(Vec::new()));
+        let sink_collected = collected.clone();
+        let mut encoder = EncodingBuffer::new(MailType::Ascii);
+        encoder.set_warn_sink(Box::new(move |warning| {
+            sink_collected.lock().unwrap().push(warning)
+        }));
+
+        assert_ok!(encoder.write_header_line(|hdl| {
+            hdl.write_str(SoftAsciiStr::from_unchecked("Subject: "))?;
+            Unstructured("Café").encode(hdl)
+        }));
+
+        assert_eq!(
+            assert_ok!(encoder.as_str()),
+            "Subject: =?utf8?Q?Caf=C3=A9?=\r\n"
+        );
+        assert_eq!(*collected.lock().unwrap(), vec![
+            EncodingWarning::EncodedWordFallback {
+                original_len: 5,
+                header: Some("Subject".to_owned())
+            }
+        ]);
+    }
+
+    #[test]
+    fn encode_latin1() {
+        let mut out = VecWriter::new(
+            EncodedWordCharset::Latin1.name(),
+            EncodedWordEncoding::QuotedPrintable
+        );
+        assert_ok!(EncodedWordEncoding::QuotedPrintable
+            .encode_with_charset("Grüße", EncodedWordCharset::Latin1, &mut out));
+        assert_eq!(out.data(), &[
+            SoftAsciiString::from_unchecked("=?ISO-8859-1?Q?Gr=FC=DFe?=")
+        ]);
+
+        let mut out = VecWriter::new(
+            EncodedWordCharset::Latin1.name(),
+            EncodedWordEncoding::Base64
+        );
+        assert_ok!(EncodedWordEncoding::Base64
+            .encode_with_charset("Grüße", EncodedWordCharset::Latin1, &mut out));
+        assert_eq!(out.data(), &[
+            SoftAsciiString::from_unchecked("=?ISO-8859-1?B?R3L832U=?=")
+        ]);
+    }
+
+    #[test]
+    fn write_encoded_word_latin1() {
+        use soft_ascii_string::SoftAsciiStr;
+        use ::encoder::EncodingBuffer;
+        use ::MailType;
+
+        let mut encoder = EncodingBuffer::new(MailType::Ascii);
+        assert_ok!(encoder.write_header_line(|hdl| {
+            hdl.write_str(SoftAsciiStr::from_unchecked("Subject: "))?;
+            write_encoded_word(
+                hdl, "Grüße", EncodedWordEncoding::QuotedPrintable, EncodedWordCharset::Latin1)
+        }));
+        assert_eq!(
+            assert_ok!(encoder.as_str()),
+            "Subject: =?ISO-8859-1?Q?Gr=FC=DFe?=\r\n"
+        );
+    }
+
+    #[test]
+    fn non_latin1_char_is_not_encodable_as_latin1() {
+        let mut out = VecWriter::new(
+            EncodedWordCharset::Latin1.name(),
+            EncodedWordEncoding::QuotedPrintable
+        );
+        let err = assert_err!(EncodedWordEncoding::QuotedPrintable
+            .encode_with_charset("a♥b", EncodedWordCharset::Latin1, &mut out));
+        assert_eq!(err.kind(), EncodingErrorKind::NotEncodable { encoding: "ISO-8859-1" });
+        assert_eq!(err.str_context(), Some("a♥b"));
+        assert_eq!(out.data(), &[SoftAsciiString::new()]);
+    }
+}
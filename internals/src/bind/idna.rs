@@ -16,6 +16,11 @@ use ::error::{EncodingError, EncodingErrorKind};
 /// if you puny code the domain `"this seems\0so;wrong"` it
 /// will return `Ok("this seems\0so;wrong")`
 ///
+/// # Error
+/// fails with `Malformed` if `idna` rejects the domain (e.g. because
+/// of an invalid puny code label), the str context contains the domain
+/// followed by the errors reported by `idna`
+///
 pub fn puny_code_domain<R: AsRef<str>>(domain: R)
     -> Result<SoftAsciiString, EncodingError>
 {
@@ -30,16 +35,22 @@ fn _puny_code_domain(domain: &str)
             //SAFE: well we converted it to ascii, so it's ascii
             Ok(SoftAsciiString::from_unchecked(asciified))
         },
-        Err(_non_informative_err) => {
-            Err(EncodingErrorKind::NotEncodable { encoding: "punycode" }.into())
-        }
+        Err(errors) => Err(idna_error(domain, errors))
     }
 }
 
+fn idna_error(domain: &str, errors: idna::uts46::Errors) -> EncodingError {
+    // `Errors` neither implements `Display` nor `Fail` so it can't be
+    // the cause of the context, instead its debug repr is kept
+    EncodingError::from(EncodingErrorKind::Malformed)
+        .with_str_context(format!("{}: {:?}", domain, errors))
+}
+
 
 #[cfg(test)]
 mod test {
     use idna;
+    use ::error::EncodingErrorKind;
     use super::puny_code_domain;
 
     #[test]
@@ -70,4 +81,14 @@ mod test {
             "xn--nt_ascii-n4a.xn--tda"
         );
     }
+
+    #[test]
+    fn invalid_domain_is_malformed() {
+        let domain = "xn--a$b.example";
+        let err = assert_err!(puny_code_domain(domain));
+        assert_eq!(err.kind(), EncodingErrorKind::Malformed);
+        let context = err.str_context().unwrap();
+        assert!(context.starts_with("xn--a$b.example: "));
+        assert!(context.len() > domain.len() + 2);
+    }
 }
\ No newline at end of file
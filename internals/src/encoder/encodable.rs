@@ -1,10 +1,14 @@
 use std::any::{Any, TypeId};
+use std::borrow::Cow;
 use std::fmt::{self, Debug};
+use std::net::IpAddr;
 use std::result::{ Result as StdResult };
 use std::sync::Arc;
 
+use soft_ascii_string::SoftAsciiStr;
+
 use ::error::EncodingError;
-use super::{EncodingWriter};
+use super::{EncodingBuffer, EncodingWriter};
 
 // can not be moved to `super::traits` as it depends on the
 // EncodingWriter defined here
@@ -87,6 +91,200 @@ impl EncodableInHeaderBoxExt for Box<EncodableInHeader+Send> {
     }
 }
 
+macro_rules! impl_encodable_in_header_for_tuple {
+    ($($name:ident),+) => (
+        /// Encodes all components of the tuple one after another.
+        impl<$($name),+> EncodableInHeader for ($($name,)+)
+            where $($name: EncodableInHeader + Clone),+
+        {
+            #[allow(non_snake_case)]
+            fn encode(&self, encoder:  &mut EncodingWriter) -> Result<(), EncodingError> {
+                let &($(ref $name,)+) = self;
+                $( $name.encode(encoder)?; )+
+                Ok(())
+            }
+
+            fn boxed_clone(&self) -> Box<EncodableInHeader> {
+                Box::new(self.clone())
+            }
+        }
+    );
+}
+
+impl_encodable_in_header_for_tuple!(A);
+impl_encodable_in_header_for_tuple!(A, B);
+impl_encodable_in_header_for_tuple!(A, B, C);
+impl_encodable_in_header_for_tuple!(A, B, C, D);
+impl_encodable_in_header_for_tuple!(A, B, C, D, E);
+impl_encodable_in_header_for_tuple!(A, B, C, D, E, F);
+impl_encodable_in_header_for_tuple!(A, B, C, D, E, F, G);
+impl_encodable_in_header_for_tuple!(A, B, C, D, E, F, G, H);
+
+/// A wrapper applying a transformation to the encoded output of a component.
+///
+/// The component is first encoded (as partial header) into a scratch
+/// buffer, then the function is applied to the result and the returned
+/// text is written. Note that places where the component marked FWS
+/// are lost in the process.
+///
+/// The transformed text is written with `write_str` if it is us-ascii
+/// and with `write_utf8` else, i.e. it fails if the transformation
+/// introduced non us-ascii text into a mail which is not internationalized.
+#[derive(Clone)]
+pub struct MapEncode<C, F> {
+    component: C,
+    func: F
+}
+
+impl<C, F> MapEncode<C, F>
+    where C: EncodableInHeader + Clone,
+          F: for<'a> Fn(&'a str) -> Cow<'a, str> + Send + Sync + Clone + 'static
+{
+    pub fn new(component: C, func: F) -> Self {
+        MapEncode { component, func }
+    }
+}
+
+impl<C, F> EncodableInHeader for MapEncode<C, F>
+    where C: EncodableInHeader + Clone,
+          F: for<'a> Fn(&'a str) -> Cow<'a, str> + Send + Sync + Clone + 'static
+{
+    fn encode(&self, encoder:  &mut EncodingWriter) -> Result<(), EncodingError> {
+        let mut scratch = EncodingBuffer::new(encoder.mail_type());
+        {
+            let mut handle = scratch.writer();
+            if let Err(err) = self.component.encode(&mut handle) {
+                handle.undo_header();
+                return Err(err);
+            }
+            handle.commit_partial_header();
+        }
+        let mapped = (self.func)(scratch.as_str()?);
+        match SoftAsciiStr::from_str(&mapped) {
+            Ok(ascii) => encoder.write_str(ascii),
+            Err(_) => encoder.write_utf8(&mapped)
+        }
+    }
+
+    fn boxed_clone(&self) -> Box<EncodableInHeader> {
+        Box::new(self.clone())
+    }
+}
+
+impl<C, F> Debug for MapEncode<C, F>
+    where C: Debug
+{
+    fn fmt(&self, fter: &mut fmt::Formatter) -> fmt::Result {
+        fter.debug_struct("MapEncode")
+            .field("component", &self.component)
+            .field("func", &"..")
+            .finish()
+    }
+}
+
+/// A component which writes nothing but fails if the guard function fails.
+///
+/// This can be used to validate a precondition in the middle of a
+/// sequence of components (e.g. a tuple), failing the encoding of
+/// the whole sequence.
+#[derive(Clone)]
+pub struct Guard<F>(F);
+
+impl<F> Guard<F>
+    where F: Fn() -> Result<(), EncodingError> + Send + Sync + Clone + 'static
+{
+    pub fn new(guard: F) -> Self {
+        Guard(guard)
+    }
+}
+
+impl<F> EncodableInHeader for Guard<F>
+    where F: Fn() -> Result<(), EncodingError> + Send + Sync + Clone + 'static
+{
+    fn encode(&self, _encoder:  &mut EncodingWriter) -> Result<(), EncodingError> {
+        (self.0)()
+    }
+
+    fn boxed_clone(&self) -> Box<EncodableInHeader> {
+        Box::new(self.clone())
+    }
+}
+
+impl<F> Debug for Guard<F> {
+    fn fmt(&self, fter: &mut fmt::Formatter) -> fmt::Result {
+        write!(fter, "Guard(..)")
+    }
+}
+
+/// A list of components encoded with a separator component between each of them.
+///
+/// A FWS is marked before and after each separator, so the line can
+/// be broken around it.
+#[derive(Debug, Clone)]
+pub struct SeparatedList {
+    pub items: Vec<Box<EncodableInHeader>>,
+    pub separator: Box<EncodableInHeader>
+}
+
+impl SeparatedList {
+    pub fn new(items: Vec<Box<EncodableInHeader>>, separator: Box<EncodableInHeader>) -> Self {
+        SeparatedList { items, separator }
+    }
+}
+
+impl EncodableInHeader for SeparatedList {
+    fn encode(&self, encoder:  &mut EncodingWriter) -> Result<(), EncodingError> {
+        for (idx, item) in self.items.iter().enumerate() {
+            if idx > 0 {
+                encoder.mark_fws_pos();
+                self.separator.encode(encoder)?;
+                encoder.mark_fws_pos();
+            }
+            item.encode(encoder)?;
+        }
+        Ok(())
+    }
+
+    fn boxed_clone(&self) -> Box<EncodableInHeader> {
+        Box::new(self.clone())
+    }
+}
+
+/// A component writing a static, already validated ascii str as is.
+///
+/// The content is written with `write_str` without validating it again,
+/// so it is meant for constant tokens. The content has to be valid
+/// for the place it is used at (e.g. only contain vchar), which
+/// the caller has to make sure of.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PreValidatedAscii(pub &'static SoftAsciiStr);
+
+impl EncodableInHeader for PreValidatedAscii {
+    fn encode(&self, encoder:  &mut EncodingWriter) -> Result<(), EncodingError> {
+        encoder.write_str(self.0)
+    }
+
+    fn boxed_clone(&self) -> Box<EncodableInHeader> {
+        Box::new(*self)
+    }
+}
+
+/// Encodes the address as domain-literal (based on RFC 5321).
+///
+/// E.g. `[1.2.3.4]` for IPv4 and `[IPv6:2001:db8::1]` for IPv6.
+impl EncodableInHeader for IpAddr {
+    fn encode(&self, encoder:  &mut EncodingWriter) -> Result<(), EncodingError> {
+        match *self {
+            IpAddr::V4(ref addr) => encoder.write_domain_literal(&addr.to_string()),
+            IpAddr::V6(ref addr) => encoder.write_domain_literal(&format!("IPv6:{}", addr))
+        }
+    }
+
+    fn boxed_clone(&self) -> Box<EncodableInHeader> {
+        Box::new(*self)
+    }
+}
+
 /// Generate a think implementing `EncodableInHeader` from an function.
 ///
 /// (Mainly used in the inside of tests.)
@@ -15,19 +15,23 @@
 //! writing tests easier. (Through it should _only_ be enabled
 //! for testing and maybe debugging in some cases).
 use std::borrow::Cow;
+use std::io;
 use std::str;
 
 use failure::Fail;
-use soft_ascii_string::{SoftAsciiStr, SoftAsciiChar};
+use soft_ascii_string::{SoftAsciiStr, SoftAsciiChar, SoftAsciiString};
 
-use grammar::is_atext;
+use grammar::{
+    is_atext, is_ctl, is_ftext, is_quotable, is_token, is_unquoted_phrase, is_vchar,
+    is_ws, contains_header_injection, validate_domain_literal
+};
 use ::utils::{
     is_utf8_continuation_byte,
     vec_insert_bytes
 };
 use ::MailType;
 use ::error::{
-    EncodingError, EncodingErrorKind,
+    EncodingError, EncodingErrorKind, Place,
     UNKNOWN, UTF_8, US_ASCII
 };
 
@@ -46,14 +50,72 @@ pub use self::encodable::*;
 pub const LINE_LEN_SOFT_LIMIT: usize = 78;
 /// as specified in RFC 5322 (mail) + RFC 5321 (smtp) not including CRLF
 pub const LINE_LEN_HARD_LIMIT: usize = 998;
+/// default length (in bytes) above which a `LongHeader` warning is emitted
+pub const DEFAULT_LONG_HEADER_THRESHOLD: usize = LINE_LEN_HARD_LIMIT;
+
+/// A warning about the encoded mail, which is not severe enough to fail the encoding.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum EncodingWarning {
+    /// A header (including folding and the terminating CRLF) is longer
+    /// than the long header threshold of the encoding buffer.
+    ///
+    /// While this is valid (as long as no line exceeds the hard limit)
+    /// some mail servers are known to have problems with very long headers.
+    LongHeader {
+        /// The name of the header, if it could be determined.
+        name: Option<String>,
+        /// The length of the header in bytes.
+        bytes: usize
+    },
+    /// Non-ascii text was written as encoded word(s) because the mail
+    /// is not internationalized.
+    EncodedWordFallback {
+        /// The length of the text (before encoding) in bytes.
+        original_len: usize,
+        /// The name of the header, if it could be determined.
+        header: Option<String>
+    }
+}
+
+/// A sink receiving warnings emitted during encoding.
+pub type WarnSink = Box<FnMut(EncodingWarning) + Send>;
+
+/// Policy for handling NUL chars (`'\0'`) in text written by components.
+///
+/// NUL is not valid in headers, components writing text from external
+/// input can use `EncodingWriter::apply_nul_policy` to handle it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NulPolicy {
+    /// fail with `Malformed` if the text contains a NUL char
+    Error,
+    /// remove all NUL chars
+    Strip,
+    /// replace all NUL chars with the given char
+    Replace(char)
+}
 
+impl Default for NulPolicy {
+    fn default() -> Self {
+        NulPolicy::Error
+    }
+}
 
 /// EncodingBuffer for a Mail providing a buffer for encodable traits.
 pub struct EncodingBuffer {
     mail_type: MailType,
     buffer: Vec<u8>,
     #[cfg(feature="traceing")]
-    pub trace: Vec<TraceToken>
+    pub trace: Vec<TraceToken>,
+    #[cfg(feature="traceing")]
+    trace_sink: Option<TraceSink>,
+    warn_sink: Option<WarnSink>,
+    long_header_threshold: usize,
+    soft_line_limit: usize,
+    normalize_fold_whitespace: bool,
+    collapse_folding_ws: bool,
+    require_body_crlf: bool,
+    nul_policy: NulPolicy,
+    has_open_handle: bool
 }
 
 impl EncodingBuffer {
@@ -64,27 +126,173 @@ impl EncodingBuffer {
             mail_type,
             buffer: Vec::new(),
             #[cfg(feature="traceing")]
-            trace: Vec::new()
+            trace: Vec::new(),
+            #[cfg(feature="traceing")]
+            trace_sink: None,
+            warn_sink: None,
+            long_header_threshold: DEFAULT_LONG_HEADER_THRESHOLD,
+            soft_line_limit: LINE_LEN_SOFT_LIMIT,
+            normalize_fold_whitespace: false,
+            collapse_folding_ws: false,
+            require_body_crlf: false,
+            nul_policy: NulPolicy::default(),
+            has_open_handle: false
         }
     }
 
+    /// Create a new buffer with space for at least `expected_bytes` bytes of output.
+    ///
+    /// This avoids repeated reallocations when writing large mails
+    /// with many headers.
+    pub fn with_capacity(mail_type: MailType, expected_bytes: usize) -> Self {
+        let mut buffer = EncodingBuffer::new(mail_type);
+        buffer.buffer.reserve(expected_bytes);
+        buffer
+    }
+
+    /// Create a new buffer reusing the allocation of the given string.
+    ///
+    /// The content of the string is discarded, only its capacity is kept.
+    /// Together with `into_buffer` this allows reusing one allocation for
+    /// encoding many mails.
+    pub fn with_buffer(mail_type: MailType, buffer: String) -> Self {
+        let mut buffer = buffer.into_bytes();
+        buffer.clear();
+        let mut encoding_buffer = EncodingBuffer::new(mail_type);
+        encoding_buffer.buffer = buffer;
+        encoding_buffer
+    }
+
+    /// Create a new buffer which passes trace tokens to the given sink.
+    ///
+    /// Instead of accumulating all trace tokens in `trace` the tokens of
+    /// each header are passed to the sink once the header is finished
+    /// (or committed) and are then removed from `trace`. This means that
+    /// `trace` only contains tokens of the header currently being written,
+    /// which keeps the memory usage bound for long running encoders while
+    /// still allowing `undo_header` to remove tokens of discarded writes.
+    #[cfg(feature="traceing")]
+    pub fn with_trace_sink(mail_type: MailType, sink: TraceSink) -> Self {
+        let mut buffer = EncodingBuffer::new(mail_type);
+        buffer.trace_sink = Some(sink);
+        buffer
+    }
+
     /// Returns the mail type for which the buffer was created.
     pub fn mail_type( &self ) -> MailType {
         self.mail_type
     }
 
+    /// Changes the mail type of the buffer.
+    ///
+    /// This is meant to be used together with `reset` to reuse the buffer
+    /// for another mail, as already written data is not re-validated.
+    pub fn set_mail_type(&mut self, mail_type: MailType) {
+        self.mail_type = mail_type;
+    }
+
+    /// Discards all written data (and trace tokens) while keeping the allocation.
+    ///
+    /// All settings (e.g. sinks, limits and policies) are kept, which
+    /// allows reusing the buffer for encoding many mails in a loop.
+    /// As the unfinished header of a leaked writer is discarded, too,
+    /// `has_open_handle` returns false afterwards.
+    pub fn reset(&mut self) {
+        self.buffer.clear();
+        self.has_open_handle = false;
+        #[cfg(feature="traceing")]
+        { self.trace.clear(); }
+    }
+
+    /// Sets the sink to which warnings emitted during encoding are passed.
+    ///
+    /// Without a sink warnings are silently dropped.
+    pub fn set_warn_sink(&mut self, sink: WarnSink) {
+        self.warn_sink = Some(sink);
+    }
+
+    /// Sets the length (in bytes) above which a `LongHeader` warning is emitted.
+    ///
+    /// Defaults to `DEFAULT_LONG_HEADER_THRESHOLD`.
+    pub fn set_long_header_threshold(&mut self, threshold: usize) {
+        self.long_header_threshold = threshold;
+    }
+
+    /// Sets the soft line length limit (in bytes, not including CRLF) used when writing headers.
+    ///
+    /// Defaults to `LINE_LEN_SOFT_LIMIT`.
+    ///
+    /// # Panic
+    /// panics if the limit is larger than `LINE_LEN_HARD_LIMIT`
+    pub fn set_soft_line_limit(&mut self, limit: usize) {
+        assert!(limit <= LINE_LEN_HARD_LIMIT, "soft limit can not exceed the hard limit");
+        self.soft_line_limit = limit;
+    }
+
+    /// Sets if folds should always be continued with a space.
+    ///
+    /// By default a line broken at a tab is continued with that tab,
+    /// if this is set the tab is replaced by a space. Folds at a place
+    /// without WS always use a space. Tabs in a quoted-string are
+    /// part of the content and never replaced.
+    pub fn set_normalize_fold_whitespace(&mut self, normalize: bool) {
+        self.normalize_fold_whitespace = normalize;
+    }
+
+    /// Sets if the FWS around a fold should be collapsed into the single space of the fold.
+    ///
+    /// By default spaces written with `write_fws` before the fold point
+    /// stay at the end of the broken line, e.g. two `write_fws` between
+    /// `"a"` and `"b"` folded at the second one become `"a \r\n b"`. If
+    /// this is set it becomes `"a\r\n b"` instead. Only spaces written
+    /// with `write_fws` are collapsed, other WS (e.g. in a quoted-string)
+    /// is part of the content and kept as is.
+    pub fn set_collapse_folding_ws(&mut self, collapse: bool) {
+        self.collapse_folding_ws = collapse;
+    }
+
+    /// Sets if text bodies have to end in CRLF.
+    ///
+    /// By default a missing trailing `"\r\n"` is appended, which can hide
+    /// bugs like a truncated body. If this is set writing such a body with
+    /// `write_text_body_unchecked` fails and `write_body_unchecked` panics
+    /// instead. Bodies written with `write_binary_body_unchecked` are not
+    /// affected.
+    pub fn set_require_body_crlf(&mut self, require: bool) {
+        self.require_body_crlf = require;
+    }
+
+    /// Sets the policy components should use for NUL chars in written text.
+    ///
+    /// Defaults to `NulPolicy::Error`.
+    pub fn set_nul_policy(&mut self, policy: NulPolicy) {
+        self.nul_policy = policy;
+    }
+
+    /// Returns true if a `EncodingWriter` created from this buffer was not dropped.
+    ///
+    /// As a writer mutably borrows the buffer this can only be the
+    /// case if the writer was leaked (e.g. using `mem::forget`), which
+    /// most likely means it's last header was neither finished nor undone.
+    pub fn has_open_handle(&self) -> bool {
+        self.has_open_handle
+    }
+
+    /// Returns all trace tokens accumulated so far, leaving an empty trace behind.
+    ///
+    /// The written content is not affected by this. As any `EncodingWriter`
+    /// borrows the buffer mutably this can not be called while a writer is
+    /// in use, so no writer can end up with outdated trace indices.
+    #[cfg(feature="traceing")]
+    pub fn take_trace(&mut self) -> Vec<TraceToken> {
+        ::std::mem::replace(&mut self.trace, Vec::new())
+    }
+
     /// returns a new EncodingWriter which contains
     /// a mutable reference to the current string buffer
     ///
     pub fn writer(&mut self) -> EncodingWriter {
-        #[cfg(not(feature="traceing"))]
-        {
-            EncodingWriter::new(self.mail_type, &mut self.buffer)
-        }
-        #[cfg(feature="traceing")]
-        {
-            EncodingWriter::new(self.mail_type, &mut self.buffer, &mut self.trace)
-        }
+        EncodingWriter::new(self)
     }
 
     /// calls the provided function with a EncodingWriter cleaning up afterwards
@@ -97,36 +305,108 @@ impl EncodingBuffer {
     pub fn write_header_line<FN>(&mut self, func: FN) -> Result<(), EncodingError>
         where FN: FnOnce(&mut EncodingWriter) -> Result<(), EncodingError>
     {
-        let mut handle  = self.writer();
-        match func(&mut handle) {
-            Ok(()) => {
-                handle.finish_header();
-                Ok(())
-            },
-            Err(e) => {
-                handle.undo_header();
-                Err(e)
+        self.write_header_line_measured(func).map(|_| ())
+    }
+
+    /// like `write_header_line` but returns the number of bytes written
+    ///
+    /// The returned length includes folding and the terminating CRLF.
+    pub fn write_header_line_measured<FN>(&mut self, func: FN) -> Result<usize, EncodingError>
+        where FN: FnOnce(&mut EncodingWriter) -> Result<(), EncodingError>
+    {
+        let start_len = self.buffer.len();
+        {
+            let mut handle  = self.writer();
+            match func(&mut handle) {
+                Ok(()) => {
+                    handle.finish_header();
+                },
+                Err(e) => {
+                    handle.undo_header();
+                    return Err(e);
+                }
             }
         }
-
+        Ok(self.buffer.len() - start_len)
     }
 
     pub fn write_blank_line(&mut self) {
         //TODO/BENCH push_str vs. extends(&[u8])
         self.buffer.extend("\r\n".as_bytes());
         #[cfg(feature="traceing")]
-        { self.trace.push(TraceToken::BlankLine); }
+        {
+            if let Some(ref mut sink) = self.trace_sink {
+                sink(&TraceToken::BlankLine);
+            } else {
+                self.trace.push(TraceToken::BlankLine);
+            }
+        }
     }
 
     /// writes a body to the internal buffer, without verifying it's correctness
+    ///
+    /// If the body doesn't end in `"\r\n"` it is appended.
+    ///
+    /// The body can be any `AsRef<[u8]>`, e.g. a `Arc<[u8]>` can be used to
+    /// share the same payload between multiple mails without cloning it.
+    /// `Arc<Vec<u8>>` does not implement `AsRef<[u8]>`, so it has to be
+    /// passed as `&**body` (i.e. as `&Vec<u8>`).
+    ///
+    /// # Panic
+    ///
+    /// Panics if the body doesn't end in `"\r\n"` and `set_require_body_crlf(true)`
+    /// was called, use `write_text_body_unchecked` to get an error instead.
     pub fn write_body_unchecked(&mut self, body: &impl AsRef<[u8]>) {
         let slice = body.as_ref();
+        assert!(
+            !self.require_body_crlf || slice.ends_with(b"\r\n"),
+            "body doesn't end in CRLF but set_require_body_crlf(true) was called"
+        );
         self.buffer.extend(slice);
         if !slice.ends_with(b"\r\n") {
             self.buffer.extend(b"\r\n");
         }
     }
 
+    /// writes a text body to the internal buffer, without verifying it's correctness
+    ///
+    /// This works like `write_body_unchecked` but respects `set_require_body_crlf`.
+    ///
+    /// # Error
+    /// fails with `Malformed` (and `Place::Body`) if the body doesn't end
+    /// in `"\r\n"` and `set_require_body_crlf(true)` was called, in
+    /// which case nothing is written; the `str_context` contains the
+    /// unterminated last line of the body
+    pub fn write_text_body_unchecked(&mut self, body: &impl AsRef<[u8]>)
+        -> Result<(), EncodingError>
+    {
+        let slice = body.as_ref();
+        if self.require_body_crlf && !slice.ends_with(b"\r\n") {
+            let last_line_start = slice.iter().rposition(|&bch| bch == b'\n')
+                .map(|idx| idx + 1)
+                .unwrap_or(0);
+            let context = format!(
+                "missing trailing CRLF after {:?}",
+                String::from_utf8_lossy(&slice[last_line_start..])
+            );
+            return Err(EncodingError::from((EncodingErrorKind::Malformed, self.mail_type))
+                .with_place_or_else(|| Some(Place::Body))
+                .with_str_context(context));
+        }
+        self.write_body_unchecked(body);
+        Ok(())
+    }
+
+    /// writes a binary body to the internal buffer, without verifying it's correctness
+    ///
+    /// Unlike `write_body_unchecked` this will _not_ append a trailing `"\r\n"`
+    /// if the body doesn't end with one. Binary bodies (e.g. when sending
+    /// with BINARYMIME) are not line based and appending anything to them
+    /// would change the payload.
+    pub fn write_binary_body_unchecked(&mut self, body: &impl AsRef<[u8]>) {
+        self.buffer.extend(body.as_ref());
+    }
+
     //TODO impl. a alt. `write_body(body,  boundaries)` which:
     // - checks the body (us-ascii or mime8bit/internationalized)
     // - checks for orphan '\r'/'\n' and 0 bytes
@@ -156,16 +436,165 @@ impl EncodingBuffer {
         Ok(self.as_str()?.to_owned())
     }
 
+    /// Converts the buffer into the underlying (utf-8) string without copying it.
+    ///
+    /// # Error
+    ///
+    /// Fails with `InvalidTextEncoding` if the buffer is not valid
+    /// utf-8, e.g. because a binary body was written to it.
+    pub fn into_buffer(self) -> Result<String, EncodingError> {
+        let mail_type = self.mail_type;
+        String::from_utf8(self.buffer)
+            .map_err(|err| {
+                EncodingError::from((
+                    err.context(EncodingErrorKind::InvalidTextEncoding {
+                        expected_encoding: UTF_8,
+                        got_encoding: UNKNOWN
+                    }),
+                    mail_type
+                ))
+            })
+    }
+
+    /// Converts the internal buffer into a `SoftAsciiString`.
+    ///
+    /// # Error
+    ///
+    /// Fails with `InvalidTextEncoding` if the buffer contains
+    /// any non us-ascii bytes.
+    pub fn to_soft_ascii_string(&self) -> Result<SoftAsciiString, EncodingError> {
+        if !self.buffer.is_ascii() {
+            return Err(EncodingError::from((
+                EncodingErrorKind::InvalidTextEncoding {
+                    expected_encoding: US_ASCII,
+                    got_encoding: UNKNOWN
+                },
+                self.mail_type()
+            )));
+        }
+        let string = self.to_string()?;
+        //SAFE: we checked that the buffer is us-ascii
+        Ok(SoftAsciiString::from_unchecked(string))
+    }
+
     /// Lossy conversion of the internal buffer to an string.
     pub fn to_string_lossy(&self) -> Cow<str> {
         String::from_utf8_lossy(self.buffer.as_slice())
     }
 
+    /// Writes the encoded data to the given output.
+    ///
+    /// # Error
+    ///
+    /// Fails with `WritingOutputFailed` (with the io error as cause)
+    /// if writing to the output fails.
+    pub fn write_to<W: io::Write>(&self, out: &mut W) -> Result<(), EncodingError> {
+        out.write_all(&self.buffer)
+            .map_err(|err| {
+                EncodingError::from((
+                    err.context(EncodingErrorKind::WritingOutputFailed),
+                    self.mail_type()
+                ))
+            })
+    }
+
     /// Return a slice view to the underlying buffer.
     pub fn as_slice(&self) -> &[u8] {
         &self.buffer
     }
 
+    /// Returns the length of the encoded mail in bytes.
+    ///
+    /// As the buffer contains the whole encoded mail this is exact,
+    /// e.g. it can be used to reject oversized mails before sending them.
+    pub fn len(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Returns true if nothing was written to the buffer.
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+
+    /// Normalizes the folds of all headers written to the buffer.
+    ///
+    /// All WS following a CRLF in the header block (i.e. before the
+    /// first blank line) is replaced by a single space, e.g. `"a\r\n\t b"`
+    /// becomes `"a\r\n b"`. Folds inside of a quoted-string are kept as
+    /// is, as the WS there is part of the content. Quoted-pairs and (nested)
+    /// comments are taken into account when looking for quoted-strings, i.e.
+    /// neither `\"` nor a `"` in a comment start or end one. The body is not
+    /// changed and neither is the trace.
+    pub fn normalize_header_folds(&mut self) {
+        let header_end = if self.buffer.starts_with(b"\r\n") {
+            0
+        } else {
+            self.buffer.windows(4)
+                .position(|window| window == b"\r\n\r\n")
+                .map(|idx| idx + 2)
+                .unwrap_or(self.buffer.len())
+        };
+
+        let is_ws_byte = |bch: u8| bch == b' ' || bch == b'\t';
+        let mut normalized = Vec::with_capacity(self.buffer.len());
+        let mut in_quoted_string = false;
+        let mut comment_depth = 0usize;
+        let mut escaped = false;
+        let mut idx = 0;
+        while idx < header_end {
+            let bch = self.buffer[idx];
+            normalized.push(bch);
+            idx += 1;
+            if escaped {
+                escaped = false;
+            } else if (in_quoted_string || comment_depth > 0) && bch == b'\\' {
+                escaped = true;
+            } else if bch == b'"' && comment_depth == 0 {
+                in_quoted_string = !in_quoted_string;
+            } else if bch == b'(' && !in_quoted_string {
+                comment_depth += 1;
+            } else if bch == b')' && !in_quoted_string && comment_depth > 0 {
+                comment_depth -= 1;
+            } else if bch == b'\n' {
+                let is_fold = idx < header_end && is_ws_byte(self.buffer[idx]);
+                if !is_fold {
+                    // a new header starts, so a unbalanced quote or
+                    // comment can't affect it
+                    in_quoted_string = false;
+                    comment_depth = 0;
+                } else if !in_quoted_string {
+                    normalized.push(b' ');
+                    while idx < header_end && is_ws_byte(self.buffer[idx]) {
+                        idx += 1;
+                    }
+                }
+            }
+        }
+        normalized.extend_from_slice(&self.buffer[header_end..]);
+        self.buffer = normalized;
+    }
+
+    /// Appends the content of another buffer to this buffer.
+    ///
+    /// This is cheaper than re-encoding e.g. the parts of a multipart
+    /// mail which where encoded independently. Under `traceing` the
+    /// trace of the other buffer is appended, too.
+    ///
+    /// # Error
+    /// fails if the other buffer has a different mail type
+    pub fn append(&mut self, other: EncodingBuffer) -> Result<(), EncodingError> {
+        if other.mail_type != self.mail_type {
+            return Err(EncodingError::from((
+                EncodingErrorKind::Other { kind: "appending buffer with different mail type" },
+                self.mail_type
+            )).with_str_context(format!("{:?}", other.mail_type)));
+        }
+        self.buffer.extend_from_slice(&other.buffer);
+        #[cfg(feature="traceing")]
+        { self.trace.extend(other.trace); }
+        Ok(())
+    }
+
 }
 
 
@@ -181,14 +610,95 @@ impl Into<(MailType, Vec<u8>)> for EncodingBuffer {
     }
 }
 
+/// Creates a buffer continuing after already encoded data.
+///
+/// The data is taken as is, i.e. it's neither validated nor is
+/// a trace created for it.
+impl From<(MailType, Vec<u8>)> for EncodingBuffer {
+    fn from((mail_type, buffer): (MailType, Vec<u8>)) -> Self {
+        let mut encoding_buffer = EncodingBuffer::new(mail_type);
+        encoding_buffer.buffer = buffer;
+        encoding_buffer
+    }
+}
+
 #[cfg(feature="traceing")]
 impl Into<(MailType, Vec<u8>, Vec<TraceToken>)> for EncodingBuffer {
     fn into(self) -> (MailType, Vec<u8>, Vec<TraceToken>) {
-        let EncodingBuffer { mail_type, buffer, trace } = self;
+        let EncodingBuffer { mail_type, buffer, trace, .. } = self;
         (mail_type, buffer, trace)
     }
 }
 
+/// Checks if the component can be encoded for the given mail type.
+///
+/// This encodes the component (as partial header) into a scratch buffer
+/// which is discarded afterwards, so only the result of the encoding is
+/// returned.
+pub fn validate_encodable(component: &EncodableInHeader, mail_type: MailType)
+    -> Result<(), EncodingError>
+{
+    let mut buffer = EncodingBuffer::new(mail_type);
+    let mut handle = buffer.writer();
+    let res = component.encode(&mut handle);
+    // there is no need to undo anything, the buffer is discarded anyway
+    handle.commit_partial_header();
+    res
+}
+
+/// Encodes the component and escapes the result so that it can be safely included in a log line.
+///
+/// `'\r'` and `'\n'` are replaced by `"\\r"` and `"\\n"` and all
+/// other control chars (including `'\x7f'`) by a `"\\xNN"` escape.
+/// The component is written as partial header, i.e. no trailing
+/// CRLF is added.
+pub fn header_value_for_log(component: &EncodableInHeader, mail_type: MailType)
+    -> Result<String, EncodingError>
+{
+    let mut buffer = EncodingBuffer::new(mail_type);
+    {
+        let mut handle = buffer.writer();
+        if let Err(err) = component.encode(&mut handle) {
+            handle.undo_header();
+            return Err(err);
+        }
+        handle.commit_partial_header();
+    }
+    let mut out = String::with_capacity(buffer.as_slice().len());
+    for ch in buffer.as_str()?.chars() {
+        match ch {
+            '\r' => out.push_str("\\r"),
+            '\n' => out.push_str("\\n"),
+            ch if is_ctl(ch) || ch == '\x7f' => out.push_str(&format!("\\x{:02x}", ch as u32)),
+            ch => out.push(ch)
+        }
+    }
+    Ok(out)
+}
+
+/// A position in the header currently written by an `EncodingWriter` which can be rolled back to.
+///
+/// Created by `EncodingWriter::savepoint` and consumed by `EncodingWriter::rollback`.
+#[derive(Debug)]
+pub struct Savepoint {
+    id: usize,
+    /// the current line up to the savepoint, folding the line at a FWS
+    /// marked before the savepoint modifies it after the savepoint was created
+    line_tail: Vec<u8>,
+    header_start_idx: usize,
+    line_start_idx: usize,
+    last_fws_idx: usize,
+    skipped_cr: bool,
+    content_since_fws: bool,
+    content_before_fws: bool,
+    fws_in_quoted_string: bool,
+    fws_spaces: Vec<usize>,
+    #[cfg(feature="traceing")]
+    trace_len: usize,
+    #[cfg(feature="traceing")]
+    fold_count: usize
+}
+
 /// A handle providing method to write to the underlying buffer
 /// keeping track of newlines the current line length and places
 /// where the line can be broken so that the soft line length
@@ -210,6 +720,15 @@ pub struct EncodingWriter<'a> {
     buffer: &'a mut Vec<u8>,
     #[cfg(feature="traceing")]
     trace: &'a mut Vec<TraceToken>,
+    #[cfg(feature="traceing")]
+    trace_sink: &'a mut Option<TraceSink>,
+    warn_sink: &'a mut Option<WarnSink>,
+    has_open_handle: &'a mut bool,
+    long_header_threshold: usize,
+    soft_line_limit: usize,
+    normalize_fold_whitespace: bool,
+    collapse_folding_ws: bool,
+    nul_policy: NulPolicy,
     mail_type: MailType,
     line_start_idx: usize,
     last_fws_idx: usize,
@@ -223,62 +742,75 @@ pub struct EncodingWriter<'a> {
     /// line).
     content_before_fws: bool,
     header_start_idx: usize,
+    /// soft line length limit used for the current header instead of the default
+    soft_limit_override: Option<usize>,
+    /// ids of the savepoints of the current header which can still be rolled back to
+    savepoints: Vec<usize>,
+    /// positions of the spaces written by `write_fws` on the current line (sorted)
+    fws_spaces: Vec<usize>,
+    /// if the last fws was marked inside of a quoted-string, i.e. the
+    /// WS following it is part of the content
+    fws_in_quoted_string: bool,
+    next_savepoint_id: usize,
+    #[cfg(feature="traceing")]
+    trace_start_idx: usize,
+    /// offsets (relative to the header start) of the line breaks inserted by folding
     #[cfg(feature="traceing")]
-    trace_start_idx: usize
+    fold_positions: Vec<usize>
 }
 
-#[cfg(feature="traceing")]
 impl<'a> Drop for EncodingWriter<'a> {
 
     fn drop(&mut self) {
-        use std::thread;
-        if !thread::panicking() &&  self.has_unfinished_parts() {
-            // we really should panic as the back buffer i.e. the mail will contain
-            // some partially written header which definitely is a bug
-            panic!("dropped Handle which partially wrote header to back buffer (use `finish_header` or `discard`)")
+        *self.has_open_handle = false;
+        #[cfg(feature="traceing")]
+        {
+            use std::thread;
+            if !thread::panicking() &&  self.has_unfinished_parts() {
+                // we really should panic as the back buffer i.e. the mail will contain
+                // some partially written header which definitely is a bug
+                panic!("dropped Handle which partially wrote header to back buffer (use `finish_header` or `discard`)")
+            }
         }
     }
 }
 
 impl<'inner> EncodingWriter<'inner> {
 
-    #[cfg(not(feature="traceing"))]
-    fn new(
-        mail_type: MailType,
-        buffer: &'inner mut Vec<u8>,
-    ) -> Self {
-        let start_idx = buffer.len();
-        EncodingWriter {
-            buffer,
-            mail_type,
-            line_start_idx: start_idx,
-            last_fws_idx: start_idx,
-            skipped_cr: false,
-            content_since_fws: false,
-            content_before_fws: false,
-            header_start_idx: start_idx
-        }
-    }
-
-    #[cfg(feature="traceing")]
-    fn new(
-        mail_type: MailType,
-        buffer: &'inner mut Vec<u8>,
-        trace: &'inner mut Vec<TraceToken>
-    ) -> Self {
-        let start_idx = buffer.len();
-        let trace_start_idx = trace.len();
+    fn new(encoding_buffer: &'inner mut EncodingBuffer) -> Self {
+        encoding_buffer.has_open_handle = true;
+        let start_idx = encoding_buffer.buffer.len();
+        #[cfg(feature="traceing")]
+        let trace_start_idx = encoding_buffer.trace.len();
         EncodingWriter {
-            buffer,
-            trace,
-            mail_type,
+            buffer: &mut encoding_buffer.buffer,
+            #[cfg(feature="traceing")]
+            trace: &mut encoding_buffer.trace,
+            #[cfg(feature="traceing")]
+            trace_sink: &mut encoding_buffer.trace_sink,
+            warn_sink: &mut encoding_buffer.warn_sink,
+            has_open_handle: &mut encoding_buffer.has_open_handle,
+            long_header_threshold: encoding_buffer.long_header_threshold,
+            soft_line_limit: encoding_buffer.soft_line_limit,
+            normalize_fold_whitespace: encoding_buffer.normalize_fold_whitespace,
+            collapse_folding_ws: encoding_buffer.collapse_folding_ws,
+            nul_policy: encoding_buffer.nul_policy,
+            mail_type: encoding_buffer.mail_type,
             line_start_idx: start_idx,
             last_fws_idx: start_idx,
             skipped_cr: false,
             content_since_fws: false,
             content_before_fws: false,
             header_start_idx: start_idx,
-            trace_start_idx
+            soft_limit_override: None,
+            savepoints: Vec::new(),
+            fws_spaces: Vec::new(),
+            fws_in_quoted_string: false,
+            next_savepoint_id: 0,
+            #[cfg(feature="traceing")]
+            trace_start_idx,
+            #[cfg(feature="traceing")]
+            fold_positions: Vec::new()
         }
     }
 
@@ -290,8 +822,27 @@ impl<'inner> EncodingWriter<'inner> {
         self.content_since_fws = false;
         self.content_before_fws = false;
         self.header_start_idx = start_idx;
+        self.soft_limit_override = None;
+        self.savepoints.clear();
+        self.fws_spaces.clear();
+        self.fws_in_quoted_string = false;
         #[cfg(feature="traceing")]
-        { self.trace_start_idx = self.trace.len(); }
+        {
+            self.trace_start_idx = self.trace.len();
+            self.fold_positions.clear();
+        }
+    }
+
+    /// Returns the positions at which the current header was folded.
+    ///
+    /// Each position is the byte offset (relative to the start of the
+    /// current header) of the `"\r\n"` inserted when folding the line.
+    ///
+    /// # Test build only
+    /// this is a diagnostic only available with the `traceing` feature
+    #[cfg(feature="traceing")]
+    pub fn fold_positions(&self) -> Vec<usize> {
+        self.fold_positions.clone()
     }
 
     /// Returns true if this type thinks we are in the process of writing a header.
@@ -300,12 +851,59 @@ impl<'inner> EncodingWriter<'inner> {
         self.buffer.len() != self.header_start_idx
     }
 
+    /// Returns everything written since the last `finish_header`/`undo_header`.
+    ///
+    /// This can be used to attach the partially written header to an
+    /// error (e.g. as str context) before it is discarded by `undo_header`.
+    pub fn snapshot_pending(&self) -> &str {
+        str::from_utf8(&self.buffer[self.header_start_idx..])
+            .expect("[BUG] EncodingWriter only writes valid utf-8")
+    }
+
     /// Returns the associated mail type.
     #[inline]
     pub fn mail_type(&self) -> MailType {
         self.mail_type
     }
 
+    /// Returns true if the char can be written in a header given the mail type.
+    ///
+    /// I.e. if it is vchar (which includes non us-ascii chars for
+    /// internationalized mails) or WS. Note that `'\r'`/`'\n'` are not
+    /// writable on their own, only as part of a CRLF starting a new line.
+    pub fn can_write_char(&self, ch: char) -> bool {
+        is_vchar(ch, self.mail_type()) || is_ws(ch)
+    }
+
+    /// Returns the policy for handling NUL chars in text.
+    #[inline]
+    pub fn nul_policy(&self) -> NulPolicy {
+        self.nul_policy
+    }
+
+    /// Applies the NUL policy to the given text.
+    ///
+    /// # Error
+    /// fails with `Malformed` if the text contains a NUL char
+    /// and the policy is `NulPolicy::Error`
+    pub fn apply_nul_policy<'t>(&self, text: &'t str) -> Result<Cow<'t, str>, EncodingError> {
+        if !text.contains('\0') {
+            return Ok(Cow::Borrowed(text));
+        }
+        match self.nul_policy {
+            NulPolicy::Error => {
+                Err(EncodingError::from((EncodingErrorKind::Malformed, self.mail_type()))
+                    .with_str_context(text))
+            },
+            NulPolicy::Strip => Ok(Cow::Owned(text.replace('\0', ""))),
+            NulPolicy::Replace(ch) => {
+                Ok(Cow::Owned(text.chars()
+                    .map(|tch| if tch == '\0' { ch } else { tch })
+                    .collect()))
+            }
+        }
+    }
+
     /// Returns true if the current line has content, i.e. any non WS char.
     #[inline]
     pub fn line_has_content(&self) -> bool {
@@ -318,6 +916,63 @@ impl<'inner> EncodingWriter<'inner> {
         self.buffer.len() - self.line_start_idx
     }
 
+    /// Returns the soft line length limit in effect for the current header.
+    ///
+    /// Lines are broken at marked FWS once they reach this length
+    /// (in bytes, not including CRLF). This is the soft line limit
+    /// of the buffer unless it was overridden for the current header.
+    #[inline]
+    pub fn soft_limit(&self) -> usize {
+        self.soft_limit_override.unwrap_or(self.soft_line_limit)
+    }
+
+    /// Returns the hard line length limit in effect (in bytes, not including CRLF).
+    #[inline]
+    pub fn hard_limit(&self) -> usize {
+        LINE_LEN_HARD_LIMIT
+    }
+
+    /// Overrides the soft line length limit for the current header.
+    ///
+    /// As lines are folded once they reach the soft limit this can be
+    /// used to raise the fill level at which folding kicks in (up to the
+    /// hard limit), e.g. to minimize the number of lines of a header.
+    ///
+    /// The override is reset when the header is finished, committed
+    /// or undone.
+    ///
+    /// # Panic
+    /// panics if the limit is larger than the hard line length limit
+    pub fn override_soft_limit(&mut self, limit: usize) {
+        assert!(limit <= self.hard_limit(), "soft limit can not exceed the hard limit");
+        self.soft_limit_override = Some(limit);
+    }
+
+    /// Returns the column of the current position in chars (not bytes).
+    ///
+    /// I.e. the number of chars written to the current line, which
+    /// is also the (0-based) column the next char will be written at.
+    pub fn current_column(&self) -> usize {
+        self.buffer[self.line_start_idx..].iter()
+            .filter(|bch| !is_utf8_continuation_byte(**bch))
+            .count()
+    }
+
+    /// Returns how many bytes can still be written to the current line before reaching the soft limit.
+    ///
+    /// This allows components to e.g. pre-chunk their content instead
+    /// of relying on the line being broken at marked FWS.
+    #[inline]
+    pub fn remaining_soft_budget(&self) -> usize {
+        self.soft_limit().saturating_sub(self.current_line_byte_length())
+    }
+
+    /// Returns how many bytes can still be written to the current line before reaching the hard limit.
+    #[inline]
+    pub fn remaining_hard_budget(&self) -> usize {
+        self.hard_limit().saturating_sub(self.current_line_byte_length())
+    }
+
     /// marks the current position a a place where a soft
     /// line break (i.e. "\r\n ") can be inserted
     ///
@@ -328,6 +983,7 @@ impl<'inner> EncodingWriter<'inner> {
         { self.trace.push(TraceToken::MarkFWS) }
         self.content_before_fws |= self.content_since_fws;
         self.content_since_fws = false;
+        self.fws_in_quoted_string = false;
         self.last_fws_idx = self.buffer.len()
     }
 
@@ -346,6 +1002,7 @@ impl<'inner> EncodingWriter<'inner> {
         let mut buffer = [0xff_u8; 4];
         let ch: char = ch.into();
         let slice = ch.encode_utf8(&mut buffer);
+        self.check_continuation_line_start(slice.as_bytes()[0])?;
         self.internal_write_char(slice)
     }
 
@@ -355,6 +1012,9 @@ impl<'inner> EncodingWriter<'inner> {
     /// - fails if the hard line length limit is breached and the
     ///   line can not be broken with soft line breaks
     /// - buffer would contain a orphan '\r' or '\n' after the write
+    /// - fails with `HeaderInjection` if a CRLF is not followed by WS
+    ///   (see `grammar::contains_header_injection`), if the CRLF and the
+    ///   char following it are in the same str nothing is written
     ///
     /// Note that in case of an error part of the content might already
     /// have been written to the buffer, therefore it is recommended
@@ -370,14 +1030,48 @@ impl<'inner> EncodingWriter<'inner> {
         self.internal_write_str(s.as_str())
     }
 
-
-    /// writes a utf8 str into a buffer for an internationalized mail
+    /// writes ascii bytes to the underlying buffer
     ///
-    /// # Error (ConditionalWriteResult)
-    /// - fails with `ConditionFailure` if the underlying MailType
-    ///    is not Internationalized
-    /// - fails with `GeneralFailure` if the hard line length limit is reached
+    /// This works like `write_str` but takes bytes which are already
+    /// known to be ascii, e.g. because they were produced by another
+    /// layer, without requiring them to be converted to a `SoftAsciiStr`.
+    ///
+    /// # Error
+    /// - fails if the hard line length limit is breached and the
+    ///   line can not be broken with soft line breaks
+    /// - buffer would contain a orphan '\r' or '\n' after the write
+    /// - fails with `InvalidTextEncoding` if any of the bytes is not ascii,
+    ///   in which case nothing is written
+    /// - fails with `HeaderInjection` like `write_str`
+    ///
+    /// # Trace (test build only)
+    /// does push `NowStr` and then can push `Text`,`CRLF`
+    ///
+    pub fn write_soft_ascii_bytes(&mut self, bytes: &[u8]) -> Result<(), EncodingError> {
+        if !bytes.is_ascii() {
+            return Err(EncodingError::from((
+                EncodingErrorKind::InvalidTextEncoding {
+                    expected_encoding: US_ASCII,
+                    got_encoding: UNKNOWN
+                },
+                self.mail_type()
+            )));
+        }
+        #[cfg(feature="traceing")]
+        { self.trace.push(TraceToken::NowStr) }
+        let s = str::from_utf8(bytes).expect("ascii is always valid utf-8");
+        self.internal_write_str(s)
+    }
+
+
+    /// writes a utf8 str into a buffer for an internationalized mail
+    ///
+    /// # Error (ConditionalWriteResult)
+    /// - fails with `ConditionFailure` if the underlying MailType
+    ///    is not Internationalized
+    /// - fails with `GeneralFailure` if the hard line length limit is reached
     /// - or if the buffer would contain a orphan '\r' or '\n' after the write
+    /// - or if a CRLF is not followed by WS (`HeaderInjection`)
     ///
     /// Note that in case of an error part of the content might already
     /// have been written to the buffer, therefore it is recommended
@@ -419,6 +1113,50 @@ impl<'inner> EncodingWriter<'inner> {
         }
     }
 
+    /// writes the chars produced by the iterator one by one
+    ///
+    /// This avoids collecting chars produced on the fly (e.g. by
+    /// case folding) into a `String` just to write it with `write_utf8`.
+    ///
+    /// # Error
+    /// - fails with `InvalidTextEncoding` if a non-ascii char is written
+    ///   and the mail is not internationalized
+    /// - fails if the hard line length limit is breached and the
+    ///   line can not be broken with soft line breaks
+    /// - buffer would contain a orphan '\r' or '\n' after the write
+    ///
+    /// Note that in case of an error part of the content might already
+    /// have been written to the buffer, therefore it is recommended
+    /// to call `undo_header` after an error.
+    ///
+    /// # Trace (test build only)
+    /// does push `NowUtf8` and then can push `Text`,`CRLF`
+    pub fn write_char_iter<I>(&mut self, iter: I) -> Result<(), EncodingError>
+        where I: IntoIterator<Item=char>
+    {
+        #[cfg(feature="traceing")]
+        { self.trace.push(TraceToken::NowUtf8) }
+        let internationalized = self.mail_type().is_internationalized();
+        let mut buffer = [0xff_u8; 4];
+        // only the first char and chars following a LF can start a line
+        let mut can_start_line = true;
+        for ch in iter {
+            if !internationalized && !ch.is_ascii() {
+                return Err(self.error_at_current_column(EncodingErrorKind::InvalidTextEncoding {
+                    expected_encoding: US_ASCII,
+                    got_encoding: UTF_8
+                }));
+            }
+            let slice = ch.encode_utf8(&mut buffer);
+            if can_start_line {
+                self.check_continuation_line_start(slice.as_bytes()[0])?;
+            }
+            can_start_line = ch == '\n';
+            self.internal_write_char(slice)?;
+        }
+        Ok(())
+    }
+
     /// Writes a str assumed to be atext if it is atext given the mail type
     ///
     /// This method is mainly an optimization as the "is atext" and is
@@ -459,6 +1197,39 @@ impl<'inner> EncodingWriter<'inner> {
         }
     }
 
+    /// Writes the text as is if it can be part of a phrase without quoting it
+    ///
+    /// This works like `write_if_atext` but allows WS between the atext
+    /// (see `grammar::is_unquoted_phrase`), which makes it usable for
+    /// e.g. display names. On a condition failure the text can be written
+    /// as quoted-string instead:
+    ///
+    /// ```ignore
+    /// handle.write_if_quotable(name)
+    ///     .handle_condition_failure(|handle| handle.write_quoted_string(name))?;
+    /// ```
+    ///
+    /// # Error (ConditionalWriteResult)
+    /// - fails with `ConditionFailure` if the text is not a unquoted phrase
+    ///   (given the mail type), this includes empty texts
+    /// - fails with `GeneralFailure` if the hard line length limit is reached and
+    ///   the line can't be broken with soft line breaks
+    ///
+    /// # Trace (test build only)
+    /// does push `NowAText` and then can push `Text`
+    ///
+    pub fn write_if_quotable<'short>(&'short mut self, s: &str)
+        -> ConditionalWriteResult<'short, 'inner>
+    {
+        if is_unquoted_phrase(s, self.mail_type()) {
+            #[cfg(feature="traceing")]
+            { self.trace.push(TraceToken::NowAText) }
+            self.internal_write_str(s).into()
+        } else {
+            ConditionalWriteResult::ConditionFailure(self)
+        }
+    }
+
     /// passes the input `s` to the condition evaluation function `cond` and
     /// then writes it _without additional checks_ to the buffer if `cond` returned
     /// true
@@ -501,6 +1272,9 @@ impl<'inner> EncodingWriter<'inner> {
     ///
     /// through is gives a different tracing its roughly equivalent.
     ///
+    /// # Error
+    /// - fails like `write_str`
+    ///
     pub fn write_str_unchecked( &mut self, s: &str) -> Result<(), EncodingError> {
         #[cfg(feature="traceing")]
         { self.trace.push(TraceToken::NowUnchecked) }
@@ -518,6 +1292,8 @@ impl<'inner> EncodingWriter<'inner> {
         #[cfg(feature="traceing")]
         { if let Some(&TraceToken::End) = self.trace.last() {}
             else { self.trace.push(TraceToken::End) } }
+        #[cfg(feature="traceing")]
+        { self.flush_trace_to_sink(); }
         self.reinit();
     }
 
@@ -538,11 +1314,24 @@ impl<'inner> EncodingWriter<'inner> {
     /// - then does push `End`
     /// - calling `finish_current()` multiple times in a row
     ///   will not generate multiple `End` tokens, just one
+    ///
+    /// # Warnings
+    /// emits a `LongHeader` warning if the header is longer
+    /// then the long header threshold of the buffer
+    ///
+    /// # Panic (debug builds only)
+    /// panics if the header is not empty but does not start with
+    /// a header name followed by `':'`, as this means the header
+    /// component forgot to write the name or the separator
     pub fn finish_header(&mut self) {
         self.start_new_line();
+        self.debug_assert_header_has_name();
+        self.warn_if_long_header();
         #[cfg(feature="traceing")]
         { if let Some(&TraceToken::End) = self.trace.last() {}
             else { self.trace.push(TraceToken::End) } }
+        #[cfg(feature="traceing")]
+        { self.flush_trace_to_sink(); }
         self.reinit();
     }
 
@@ -562,6 +1351,67 @@ impl<'inner> EncodingWriter<'inner> {
         self.reinit();
     }
 
+    /// creates a savepoint at the current position in the current header
+    ///
+    /// Unlike `undo_header` rolling back to a savepoint only undoes
+    /// the writes done after the savepoint was created, which allows
+    /// components to abandon a optional sub-part they started writing.
+    pub fn savepoint(&mut self) -> Savepoint {
+        let id = self.next_savepoint_id;
+        self.next_savepoint_id += 1;
+        self.savepoints.push(id);
+        Savepoint {
+            id,
+            line_tail: self.buffer[self.line_start_idx..].to_owned(),
+            header_start_idx: self.header_start_idx,
+            line_start_idx: self.line_start_idx,
+            last_fws_idx: self.last_fws_idx,
+            skipped_cr: self.skipped_cr,
+            content_since_fws: self.content_since_fws,
+            content_before_fws: self.content_before_fws,
+            fws_in_quoted_string: self.fws_in_quoted_string,
+            fws_spaces: self.fws_spaces.clone(),
+            #[cfg(feature="traceing")]
+            trace_len: self.trace.len(),
+            #[cfg(feature="traceing")]
+            fold_count: self.fold_positions.len()
+        }
+    }
+
+    /// undoes all writes to the internal buffer since the savepoint was created
+    ///
+    /// Savepoints created after the given savepoint can no longer
+    /// be rolled back to.
+    ///
+    /// # Trace (test build only)
+    /// also removes tokens pushed since the savepoint was created
+    ///
+    /// # Panic
+    /// panics if the header the savepoint was created in was already
+    /// finished/undone or if a savepoint created before it was rolled back to
+    pub fn rollback(&mut self, savepoint: Savepoint) {
+        let stack_idx = self.savepoints.iter().rposition(|id| *id == savepoint.id);
+        let stack_idx = match stack_idx {
+            Some(idx) if savepoint.header_start_idx == self.header_start_idx => idx,
+            _ => panic!("savepoint is no longer valid")
+        };
+        self.savepoints.truncate(stack_idx);
+        self.buffer.truncate(savepoint.line_start_idx);
+        self.buffer.extend_from_slice(&savepoint.line_tail);
+        #[cfg(feature="traceing")]
+        {
+            self.trace.truncate(savepoint.trace_len);
+            self.fold_positions.truncate(savepoint.fold_count);
+        }
+        self.line_start_idx = savepoint.line_start_idx;
+        self.last_fws_idx = savepoint.last_fws_idx;
+        self.skipped_cr = savepoint.skipped_cr;
+        self.content_since_fws = savepoint.content_since_fws;
+        self.content_before_fws = savepoint.content_before_fws;
+        self.fws_in_quoted_string = savepoint.fws_in_quoted_string;
+        self.fws_spaces = savepoint.fws_spaces;
+    }
+
 
 
     //---------------------------------------------------------------------------------------------/
@@ -574,14 +1424,156 @@ impl<'inner> EncodingWriter<'inner> {
     /// Note that it can not fail a you just pushed
     /// a place to brake the line before writing a space.
     ///
-    /// Note that currently soft line breaks will not
-    /// collapse whitespace. As such if you use `write_fws`
-    /// and then the line is broken at that position it will
-    /// start with two spaces (one from `\r\n ` and one which
-    /// had been there before).
+    /// If the line is broken at this position the written
+    /// space is used as the space of the fold, i.e. only
+    /// `"\r\n"` is inserted. Spaces written by `write_fws` before
+    /// the space are kept at the end of the broken line unless the
+    /// buffer was set to collapse folding WS.
     pub fn write_fws(&mut self) {
         self.mark_fws_pos();
-        let _ = self.write_char(SoftAsciiChar::from_unchecked(' '));
+        if self.write_char(SoftAsciiChar::from_unchecked(' ')).is_ok() {
+            self.fws_spaces.push(self.buffer.len() - 1);
+        }
+    }
+
+    /// writes a domain-literal wrapping the given content in `'['`, `']'`
+    ///
+    /// The content (e.g. `"192.0.2.1"` or `"IPv6:2001:db8::1"`) is written
+    /// as is, i.e. no IDNA processing is done.
+    ///
+    /// # Error
+    /// - fails with `Malformed` if the content is not dtext (given the mail
+    ///   type), e.g. if it contains `'['`, `']'` or `'\'`
+    /// - fails if the hard line length limit is breached
+    pub fn write_domain_literal(&mut self, literal: &str) -> Result<(), EncodingError> {
+        validate_domain_literal(literal, self.mail_type())?;
+        self.write_char(SoftAsciiChar::from_unchecked('['))?;
+        self.write_str_unchecked(literal)?;
+        self.write_char(SoftAsciiChar::from_unchecked(']'))
+    }
+
+    /// writes the text as quoted-string, escaping `'"'` and `'\'` with quoted-pairs
+    ///
+    /// E.g. `She said "hi"` is written as `"She said \"hi\""`.
+    ///
+    /// The positions before WS in the quoted-string are marked as FWS,
+    /// so that long quoted-strings (e.g. phrases) can be folded.
+    ///
+    /// # Error
+    /// - fails with `Malformed` if the text contains a char which
+    ///   can not be quoted (given the mail type), e.g. `'\r'`, in
+    ///   which case nothing is written
+    /// - fails if the hard line length limit is breached
+    pub fn write_quoted_string(&mut self, text: &str) -> Result<(), EncodingError> {
+        let mail_type = self.mail_type();
+        if !text.chars().all(|ch| is_quotable(ch, mail_type)) {
+            return Err(EncodingError::from((EncodingErrorKind::Malformed, mail_type))
+                .with_str_context(text));
+        }
+        let mut quoted = String::with_capacity(text.len() + 2);
+        quoted.push('"');
+        for ch in text.chars() {
+            if ch == ' ' || ch == '\t' {
+                self.write_str_unchecked(&quoted)?;
+                quoted.clear();
+                self.mark_fws_pos();
+                self.fws_in_quoted_string = true;
+            } else if ch == '"' || ch == '\\' {
+                quoted.push('\\');
+            }
+            quoted.push(ch);
+        }
+        quoted.push('"');
+        self.write_str_unchecked(&quoted)
+    }
+
+    /// writes a MIME parameter value, as token if possible and as quoted-string else
+    ///
+    /// E.g. `utf-8` is written as is, but `a b` is written as `"a b"`.
+    ///
+    /// # Error
+    /// fails like `write_quoted_string` if the value needs to be
+    /// quoted but can not be quoted
+    pub fn write_param_value(&mut self, value: &str) -> Result<(), EncodingError> {
+        if is_token(value) {
+            // is_token implies us-ascii
+            self.write_str(SoftAsciiStr::from_unchecked(value))
+        } else {
+            self.write_quoted_string(value)
+        }
+    }
+
+    /// writes a ascii token which can be folded at any position
+    ///
+    /// This is meant for long tokens without any natural place to
+    /// break them, e.g. the base64 encoded signature in a `DKIM-Signature`
+    /// header. Before each char a FWS is marked, so if the line is broken
+    /// a `"\r\n "` is inserted _into_ the token. As such this must only
+    /// be used where FWS in the token is ignored (like it is for base64
+    /// in `DKIM-Signature`).
+    ///
+    /// # Error
+    /// - fails if the buffer would contain a orphan `'\r'` or `'\n'`
+    ///   after the write
+    pub fn write_foldable_token(&mut self, token: &SoftAsciiStr) -> Result<(), EncodingError> {
+        for ch in token.chars() {
+            self.mark_fws_pos();
+            self.write_char(ch)?;
+        }
+        Ok(())
+    }
+
+    /// writes words separated by spaces at which the line can be folded
+    ///
+    /// Empty words are skipped.
+    ///
+    /// # Error
+    /// - fails with `Malformed` if a word contains a char which is
+    ///   not vchar (given the mail type), e.g. WS or non us-ascii chars
+    ///   in a non internationalized mail, nothing of the word is written
+    /// - fails if the hard line length limit is breached
+    pub fn write_words<'a, I>(&mut self, words: I) -> Result<(), EncodingError>
+        where I: IntoIterator<Item=&'a str>
+    {
+        let mail_type = self.mail_type();
+        let mut is_first = true;
+        for word in words {
+            if word.is_empty() {
+                continue;
+            }
+            if !word.chars().all(|ch| is_vchar(ch, mail_type)) {
+                return Err(EncodingError::from((EncodingErrorKind::Malformed, mail_type))
+                    .with_str_context(word));
+            }
+            if !is_first {
+                self.write_fws();
+            }
+            self.write_str_unchecked(word)?;
+            is_first = false;
+        }
+        Ok(())
+    }
+
+    /// Returns the name of the current header, i.e. the ftext before the first `':'`.
+    ///
+    /// Returns `None` if no (valid) header name was written yet.
+    pub fn current_header_name(&self) -> Option<&str> {
+        let header = &self.buffer[self.header_start_idx..];
+        let end = header.iter().position(|bch| *bch == b':')?;
+        let name = &header[..end];
+        if !name.is_empty() && name.iter().all(|bch| is_ftext(*bch as char)) {
+            // ftext is ascii, so this can not fail
+            str::from_utf8(name).ok()
+        } else {
+            None
+        }
+    }
+
+    /// passes the warning to the warn sink of the buffer (if there is one)
+    pub fn warn(&mut self, warning: EncodingWarning) {
+        if let Some(ref mut sink) = *self.warn_sink {
+            sink(warning);
+        }
     }
 
 
@@ -589,6 +1581,39 @@ impl<'inner> EncodingWriter<'inner> {
     //---------------------------------------------------------------------------------------------/
     //-///////////////////////////          private methods               ////////////////////////-/
 
+    /// passes all trace tokens of the current header to the trace sink (if there is one)
+    #[cfg(feature="traceing")]
+    fn flush_trace_to_sink(&mut self) {
+        if let Some(ref mut sink) = *self.trace_sink {
+            for token in self.trace.drain(self.trace_start_idx..) {
+                sink(&token);
+            }
+        }
+    }
+
+    /// panics in debug builds if the current (non empty) header has no name
+    #[inline]
+    fn debug_assert_header_has_name(&self) {
+        if cfg!(debug_assertions)
+            && self.has_unfinished_parts()
+            && self.current_header_name().is_none()
+        {
+            panic!(
+                "[BUG] header does not start with \"<name>:\" (missing name or colon?): {:?}",
+                String::from_utf8_lossy(&self.buffer[self.header_start_idx..])
+            );
+        }
+    }
+
+    /// emits a `LongHeader` warning if the current header exceeds the threshold
+    fn warn_if_long_header(&mut self) {
+        let bytes = self.buffer.len() - self.header_start_idx;
+        if bytes > self.long_header_threshold && self.warn_sink.is_some() {
+            let name = self.current_header_name().map(|name| name.to_owned());
+            self.warn(EncodingWarning::LongHeader { name, bytes });
+        }
+    }
+
     /// this might partial write some data and then fail.
     /// while we could implement a undo option it makes
     /// little sense for the use case the generally available
@@ -597,6 +1622,12 @@ impl<'inner> EncodingWriter<'inner> {
         if s.is_empty() {
             return Ok(());
         }
+        if contains_header_injection(s) {
+            return Err(EncodingError::from((EncodingErrorKind::HeaderInjection, self.mail_type()))
+                .with_str_context(s));
+        }
+        // CRLFs inside of `s` are covered by the check above
+        self.check_continuation_line_start(s.as_bytes()[0])?;
         //TODO I think I wrote a iterator for this somewhere
         let mut start = 0;
         // the first byte is never a continuation byte so we start
@@ -615,6 +1646,33 @@ impl<'inner> EncodingWriter<'inner> {
         Ok(())
     }
 
+    /// Returns true if a CRLF was written and nothing was written after it.
+    ///
+    /// Anything but WS written at this point would start a new header
+    /// (or the body), folds always start the new line with a WS.
+    fn is_at_start_of_continuation_line(&self) -> bool {
+        self.line_start_idx != self.header_start_idx
+            && self.buffer.len() == self.line_start_idx
+    }
+
+    /// fails with `HeaderInjection` if writing `first_byte` would start a new header
+    ///
+    /// This has to be called with the first byte of each write (and of
+    /// each char following a LF written char by char).
+    fn check_continuation_line_start(&self, first_byte: u8) -> Result<(), EncodingError> {
+        if first_byte != b' ' && first_byte != b'\t' && self.is_at_start_of_continuation_line() {
+            Err(self.error_at_current_column(EncodingErrorKind::HeaderInjection))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// creates an error of given kind with the current column as str context
+    fn error_at_current_column(&self, kind: EncodingErrorKind) -> EncodingError {
+        EncodingError::from((kind, self.mail_type()))
+            .with_str_context(format!("at column {}", self.current_column()))
+    }
+
     /// if the line has at last one non-WS char a new line
     /// will be started by adding `\r\n` if the current line
     /// only consists of WS then a new line will be started by
@@ -643,20 +1701,39 @@ impl<'inner> EncodingWriter<'inner> {
         self.content_since_fws = false;
         self.content_before_fws = false;
         self.last_fws_idx = self.line_start_idx;
+        self.fws_spaces.clear();
 
     }
 
-    fn break_line_on_fws(&mut self) -> bool {
+    /// `next_byte` is the first byte of the char which is about to be written
+    fn break_line_on_fws(&mut self, next_byte: u8) -> bool {
         if self.content_before_fws && self.last_fws_idx > self.line_start_idx {
-            //INDEX_SAFE: self.content_before_fws is only true if there is at last one char
-            // if so self.last_ws_idx does not point at the end of the buffer but inside
-            let newline = match self.buffer[self.last_fws_idx] {
-                b' ' | b'\t' => "\r\n",
+            if self.collapse_folding_ws {
+                self.remove_fws_spaces_around_fold();
+            }
+            // self.last_fws_idx can point at the end of the buffer if the
+            // fws was marked directly before the char currently written,
+            // if that char is a space (e.g. written by `write_fws`) it is
+            // used as the space of the fold
+            let fold_byte = self.buffer.get(self.last_fws_idx).cloned();
+            let newline = match fold_byte {
+                Some(b' ') => "\r\n",
+                None if next_byte == b' ' => "\r\n",
+                Some(b'\t') => {
+                    // a tab in a quoted-string is content and can not be replaced
+                    if self.normalize_fold_whitespace && !self.fws_in_quoted_string {
+                        self.buffer[self.last_fws_idx] = b' ';
+                    }
+                    "\r\n"
+                },
                 _ => "\r\n "
             };
 
             vec_insert_bytes(&mut self.buffer, self.last_fws_idx, newline.as_bytes());
+            #[cfg(feature="traceing")]
+            { self.fold_positions.push(self.last_fws_idx - self.header_start_idx); }
             self.line_start_idx = self.last_fws_idx + 2;
+            self.fws_spaces.clear();
             // no need last_fws can be < line_start but
             //self.last_fws_idx = self.line_start_idx;
             self.content_before_fws = false;
@@ -668,6 +1745,37 @@ impl<'inner> EncodingWriter<'inner> {
         }
     }
 
+    /// removes the spaces written by `write_fws` directly before and after the last fws
+    ///
+    /// Other WS, e.g. in a quoted-string, is part of the content and kept.
+    /// Must only be called if `break_line_on_fws` would break the line.
+    fn remove_fws_spaces_around_fold(&mut self) {
+        let (start, end) = {
+            // fws_spaces is sorted, so the spaces before the fws are directly
+            // before `split` and the ones after it start at `split`
+            let split = match self.fws_spaces.binary_search(&self.last_fws_idx) {
+                Ok(idx) | Err(idx) => idx
+            };
+            let mut start = self.last_fws_idx;
+            for &space_idx in self.fws_spaces[..split].iter().rev() {
+                if space_idx + 1 != start {
+                    break;
+                }
+                start = space_idx;
+            }
+            let mut end = self.last_fws_idx;
+            for &space_idx in &self.fws_spaces[split..] {
+                if space_idx != end {
+                    break;
+                }
+                end += 1;
+            }
+            (start, end)
+        };
+        self.buffer.drain(start..end);
+        self.last_fws_idx = start;
+    }
+
     /// # Constraints
     ///
     /// `unchecked_utf8_char` is expected to be exactly
@@ -691,19 +1799,13 @@ impl<'inner> EncodingWriter<'inner> {
             if self.skipped_cr {
                 self.start_new_line()
             } else {
-                ec_bail!(
-                    mail_type: self.mail_type(),
-                    kind: Malformed
-                );
+                return Err(self.error_at_current_column(EncodingErrorKind::Malformed));
             }
             self.skipped_cr = false;
             return Ok(());
         } else {
             if self.skipped_cr {
-                ec_bail!(
-                    mail_type: self.mail_type(),
-                    kind: Malformed
-                );
+                return Err(self.error_at_current_column(EncodingErrorKind::Malformed));
             }
             if bch == b'\r' {
                 self.skipped_cr = true;
@@ -713,13 +1815,11 @@ impl<'inner> EncodingWriter<'inner> {
             }
         }
 
-        if self.current_line_byte_length() >= LINE_LEN_SOFT_LIMIT {
-            if !self.break_line_on_fws() {
-                if self.buffer.len() == LINE_LEN_HARD_LIMIT {
-                    ec_bail!(
-                        mail_type: self.mail_type(),
-                        kind: HardLineLengthLimitBreached
-                    );
+        if self.current_line_byte_length() >= self.soft_limit() {
+            if !self.break_line_on_fws(bch) {
+                if self.current_line_byte_length() >= self.hard_limit() {
+                    return Err(self.error_at_current_column(
+                        EncodingErrorKind::HardLineLengthLimitBreached));
                 }
             }
         }
@@ -894,7 +1994,7 @@ mod test {
         #[test]
         fn is_implemented_for_closures() {
             let closure = enc_func!(|handle: &mut EncodingWriter| {
-                handle.write_utf8("hy ho")
+                handle.write_utf8("X: hy ho")
             });
 
             let mut encoder = EncodingBuffer::new(MailType::Internationalized);
@@ -905,16 +2005,83 @@ mod test {
             }
             assert_eq!(encoder.trace.as_slice(), &[
                 NowUtf8,
-                Text("hy ho".into()),
+                Text("X: hy ho".into()),
                 CRLF,
                 End
             ])
         }
+
+        #[test]
+        fn validate_encodable_ok() {
+            let component = enc_func!(|handle: &mut EncodingWriter| {
+                handle.write_utf8("X-A: hy ho")
+            });
+            assert_ok!(validate_encodable(&component, MailType::Internationalized));
+        }
+
+        #[test]
+        fn validate_encodable_err() {
+            let component = enc_func!(|handle: &mut EncodingWriter| {
+                handle.write_utf8("X-A: hy ho")
+            });
+            let err = assert_err!(validate_encodable(&component, MailType::Ascii));
+            assert_eq!(err.kind(), EncodingErrorKind::InvalidTextEncoding {
+                expected_encoding: US_ASCII,
+                got_encoding: UTF_8
+            });
+        }
+
+        #[test]
+        fn header_value_for_log_escapes_newlines() {
+            let component = enc_func!(|handle: &mut EncodingWriter| {
+                handle.write_utf8("X-A: a\r\n b\tc")
+            });
+            let logged = assert_ok!(header_value_for_log(&component, MailType::Internationalized));
+            assert_eq!(logged, "X-A: a\\r\\n b\\x09c");
+        }
+
+        #[test]
+        fn failing_guard_aborts_encoding() {
+            fn display_name_missing() -> Result<(), EncodingError> {
+                Err(EncodingError::from(EncodingErrorKind::Malformed)
+                    .with_str_context("display name is missing"))
+            }
+            let component = (
+                enc_func!(|handle: &mut EncodingWriter| {
+                    handle.write_str(SoftAsciiStr::from_unchecked("X-A: a"))
+                }),
+                Guard::new(display_name_missing),
+                enc_func!(|handle: &mut EncodingWriter| {
+                    handle.write_str(SoftAsciiStr::from_unchecked(" b"))
+                })
+            );
+
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            let err = assert_err!(encoder.write_header_line(|hdl| component.encode(hdl)));
+            assert_eq!(err.str_context(), Some("display name is missing"));
+            assert_eq!(encoder.as_slice(), b"");
+        }
+
+        #[test]
+        fn snapshot_pending_in_error_context() {
+            let component = enc_func!(|handle: &mut EncodingWriter| {
+                handle.write_str(SoftAsciiStr::from_unchecked("X-A: abc"))?;
+                let err = EncodingError::from(EncodingErrorKind::Malformed)
+                    .with_str_context(handle.snapshot_pending());
+                Err(err)
+            });
+
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            let err = assert_err!(encoder.write_header_line(|hdl| component.encode(hdl)));
+            assert_eq!(err.str_context(), Some("X-A: abc"));
+            assert_eq!(encoder.as_slice(), b"");
+        }
     }
 
 
     mod EncodingBuffer {
         #![allow(non_snake_case)]
+        use ::error::{US_ASCII, UTF_8, UNKNOWN};
         use super::*;
         use super::{ _Encoder as EncodingBuffer };
 
@@ -924,6 +2091,79 @@ mod test {
             assert_eq!(encoder.mail_type(), MailType::Internationalized);
         }
 
+        #[test]
+        fn with_capacity() {
+            let mut encoder = EncodingBuffer::with_capacity(MailType::Ascii, 1024);
+            assert_eq!(encoder.mail_type(), MailType::Ascii);
+            assert!(encoder.buffer.capacity() >= 1024);
+
+            let ptr = encoder.buffer.as_ptr();
+            for _ in 0..20 {
+                assert_ok!(encoder.write_header_line(|hdl| {
+                    hdl.write_str(SoftAsciiStr::from_unchecked("X-Some-Header: some value"))
+                }));
+            }
+            // no reallocation happened
+            assert_eq!(encoder.buffer.as_ptr(), ptr);
+        }
+
+        #[test]
+        fn to_soft_ascii_string() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            assert_ok!(encoder.write_header_line(|hdl| {
+                hdl.write_str(SoftAsciiStr::from_unchecked("X-A: b"))
+            }));
+            let string = assert_ok!(encoder.to_soft_ascii_string());
+            assert_eq!(string.as_str(), "X-A: b\r\n");
+        }
+
+        #[test]
+        fn to_soft_ascii_string_fails_on_non_ascii() {
+            let mut encoder = EncodingBuffer::new(MailType::Internationalized);
+            assert_ok!(encoder.write_header_line(|hdl| {
+                hdl.write_utf8("X-A: ❤")
+            }));
+            let err = assert_err!(encoder.to_soft_ascii_string());
+            assert_eq!(err.kind(), EncodingErrorKind::InvalidTextEncoding {
+                expected_encoding: US_ASCII,
+                got_encoding: UNKNOWN
+            });
+        }
+
+        #[test]
+        fn write_to() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            assert_ok!(encoder.write_header_line(|hdl| {
+                hdl.write_str(SoftAsciiStr::from_unchecked("X-A: b"))
+            }));
+            encoder.write_blank_line();
+            encoder.write_body_unchecked(&"body");
+
+            let mut out = Vec::new();
+            assert_ok!(encoder.write_to(&mut out));
+            assert_eq!(out, encoder.as_slice());
+        }
+
+        #[test]
+        fn write_to_failing_output() {
+            use std::io;
+
+            struct FailingWriter;
+            impl io::Write for FailingWriter {
+                fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+                    Err(io::Error::new(io::ErrorKind::BrokenPipe, "closed"))
+                }
+                fn flush(&mut self) -> io::Result<()> {
+                    Ok(())
+                }
+            }
+
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            encoder.write_blank_line();
+            let err = assert_err!(encoder.write_to(&mut FailingWriter));
+            assert_eq!(err.kind(), EncodingErrorKind::WritingOutputFailed);
+        }
+
         #[test]
         fn write_body_unchecked() {
             let mut encoder = EncodingBuffer::new(MailType::Ascii);
@@ -943,31 +2183,464 @@ mod test {
                 ).as_bytes()
             )
         }
-    }
 
+        #[test]
+        fn from_mail_type_and_vec_round_trip() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            assert_ok!(encoder.write_header_line(|hdl| {
+                hdl.write_str(SoftAsciiStr::from_unchecked("X-A: b"))
+            }));
+            let (mail_type, buffer): (MailType, Vec<u8>) = encoder.into();
 
-    mod EncodingWriter {
-        #![allow(non_snake_case)]
-        use std::mem;
+            let mut encoder = EncodingBuffer::from((mail_type, buffer));
+            encoder.write_blank_line();
+            encoder.write_body_unchecked(&"body");
 
-        use super::*;
-        use super::{ _Encoder as EncodingBuffer };
+            assert_eq!(encoder.mail_type(), MailType::Ascii);
+            assert_eq!(encoder.as_slice(), b"X-A: b\r\n\r\nbody\r\n");
+        }
 
         #[test]
-        fn commit_partial_and_drop_does_not_panic() {
+        fn has_open_handle() {
             let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            assert_not!(encoder.has_open_handle());
             {
                 let mut handle = encoder.writer();
-                assert_ok!(handle.write_str(SoftAsciiStr::from_unchecked("12")));
-                handle.commit_partial_header();
+                assert_ok!(handle.write_str(SoftAsciiStr::from_unchecked("X-A: b")));
+                handle.finish_header();
             }
-            assert_eq!(encoder.as_slice(), b"12");
+            assert_not!(encoder.has_open_handle());
+            {
+                let handle = encoder.writer();
+                ::std::mem::forget(handle);
+            }
+            assert!(encoder.has_open_handle());
         }
 
         #[test]
-        fn undo_does_undo() {
+        fn require_body_crlf() {
             let mut encoder = EncodingBuffer::new(MailType::Ascii);
-            {
+            encoder.set_require_body_crlf(true);
+
+            let err = assert_err!(encoder.write_text_body_unchecked(&"line\r\ntruncated"));
+            assert_eq!(err.kind(), EncodingErrorKind::Malformed);
+            assert_eq!(err.str_context(), Some("missing trailing CRLF after \"truncated\""));
+            assert_eq!(encoder.as_slice(), b"");
+
+            assert_ok!(encoder.write_text_body_unchecked(&"complete\r\n"));
+            encoder.write_body_unchecked(&"also complete\r\n");
+            assert_eq!(encoder.as_slice(), b"complete\r\nalso complete\r\n");
+        }
+
+        #[test]
+        #[should_panic]
+        fn require_body_crlf_applies_to_write_body_unchecked() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            encoder.set_require_body_crlf(true);
+            encoder.write_body_unchecked(&"truncated");
+        }
+
+        #[test]
+        fn write_string_body_unchecked() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            assert_ok!(encoder.write_header_line(|hdl| {
+                hdl.write_str(SoftAsciiStr::from_unchecked("X-A: b"))
+            }));
+            encoder.write_blank_line();
+            encoder.write_body_unchecked(&"Hello".to_string());
+
+            assert_eq!(encoder.as_slice(), b"X-A: b\r\n\r\nHello\r\n");
+        }
+
+        #[test]
+        fn write_binary_body_unchecked() {
+            let mut encoder = EncodingBuffer::new(MailType::Mime8BitEnabled);
+            let body = &[0x00_u8, 0xff, b'\r', 0x10];
+
+            encoder.write_blank_line();
+            encoder.write_binary_body_unchecked(body);
+
+            assert_eq!(encoder.as_slice(), &[b'\r', b'\n', 0x00, 0xff, b'\r', 0x10]);
+        }
+
+        #[test]
+        fn long_header_emits_warning() {
+            use std::sync::{Arc, Mutex};
+            use ::encoder::EncodingWarning;
+
+            let collected = Arc::new(Mutex::new(Vec::new()));
+            let sink_collected = collected.clone();
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            encoder.set_long_header_threshold(20);
+            encoder.set_warn_sink(Box::new(move |warning| {
+                sink_collected.lock().unwrap().push(warning)
+            }));
+
+            assert_ok!(encoder.write_header_line(|hdl| {
+                hdl.write_str(SoftAsciiStr::from_unchecked("X-Short: 12"))
+            }));
+            assert_ok!(encoder.write_header_line(|hdl| {
+                hdl.write_str(SoftAsciiStr::from_unchecked("X-Long: 0123456789abcdef"))
+            }));
+
+            assert_eq!(*collected.lock().unwrap(), vec![
+                EncodingWarning::LongHeader {
+                    name: Some("X-Long".to_owned()),
+                    bytes: 26
+                }
+            ]);
+        }
+
+        #[test]
+        fn with_trace_sink() {
+            use std::sync::{Arc, Mutex};
+
+            let collected = Arc::new(Mutex::new(Vec::new()));
+            let sink_collected = collected.clone();
+            let mut encoder = EncodingBuffer::with_trace_sink(
+                MailType::Internationalized,
+                Box::new(move |token: &::encoder::TraceToken| {
+                    sink_collected.lock().unwrap().push(token.clone())
+                })
+            );
+
+            assert_ok!(encoder.write_header_line(|hdl| {
+                hdl.write_utf8("X-A: 12")
+            }));
+            encoder.write_blank_line();
+
+            assert_eq!(encoder.trace, vec![]);
+            assert_eq!(*collected.lock().unwrap(), vec![
+                NowUtf8,
+                Text("X-A: 12".into()),
+                CRLF,
+                End,
+                BlankLine
+            ]);
+        }
+
+        #[test]
+        fn take_trace() {
+            let mut encoder = EncodingBuffer::new(MailType::Internationalized);
+            assert_ok!(encoder.write_header_line(|hdl| {
+                hdl.write_utf8("X-A: 12")
+            }));
+
+            let trace = encoder.take_trace();
+            assert_eq!(trace, vec![
+                NowUtf8,
+                Text("X-A: 12".into()),
+                CRLF,
+                End
+            ]);
+            assert_eq!(encoder.trace, vec![]);
+
+            assert_ok!(encoder.write_header_line(|hdl| {
+                hdl.write_utf8("X-B: 3")
+            }));
+            assert_eq!(encoder.trace, vec![
+                NowUtf8,
+                Text("X-B: 3".into()),
+                CRLF,
+                End
+            ]);
+            assert_eq!(encoder.as_slice(), b"X-A: 12\r\nX-B: 3\r\n");
+        }
+
+        #[test]
+        fn default_soft_line_limit() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            assert_ok!(encoder.write_header_line(|hdl| {
+                hdl.write_str(SoftAsciiStr::from_unchecked("X: "))?;
+                hdl.write_str(SoftAsciiStr::from_unchecked(&"a".repeat(60)))?;
+                hdl.write_fws();
+                hdl.write_str(SoftAsciiStr::from_unchecked(&"b".repeat(10)))
+            }));
+            assert_eq!(
+                assert_ok!(encoder.as_str()),
+                format!("X: {} {}\r\n", "a".repeat(60), "b".repeat(10))
+            );
+        }
+
+        #[test]
+        fn set_soft_line_limit() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            encoder.set_soft_line_limit(72);
+            assert_ok!(encoder.write_header_line(|hdl| {
+                hdl.write_str(SoftAsciiStr::from_unchecked("X: "))?;
+                hdl.write_str(SoftAsciiStr::from_unchecked(&"a".repeat(60)))?;
+                hdl.write_fws();
+                hdl.write_str(SoftAsciiStr::from_unchecked(&"b".repeat(10)))
+            }));
+            assert_eq!(
+                assert_ok!(encoder.as_str()),
+                format!("X: {}\r\n {}\r\n", "a".repeat(60), "b".repeat(10))
+            );
+        }
+
+        #[test]
+        #[should_panic]
+        fn soft_line_limit_can_not_exceed_hard_limit() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            encoder.set_soft_line_limit(999);
+        }
+
+        #[test]
+        fn append() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            assert_ok!(encoder.write_header_line(|hdl| {
+                hdl.write_str(SoftAsciiStr::from_unchecked("X: a"))
+            }));
+            let mut other = EncodingBuffer::new(MailType::Ascii);
+            assert_ok!(other.write_header_line(|hdl| {
+                hdl.write_str(SoftAsciiStr::from_unchecked("Y: b"))
+            }));
+            let expected_trace = {
+                let mut trace = encoder.trace.clone();
+                trace.extend(other.trace.iter().cloned());
+                trace
+            };
+
+            assert_ok!(encoder.append(other));
+
+            assert_eq!(assert_ok!(encoder.as_str()), "X: a\r\nY: b\r\n");
+            assert_eq!(encoder.trace, expected_trace);
+        }
+
+        #[test]
+        fn append_with_different_mail_type_fails() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            let other = EncodingBuffer::new(MailType::Internationalized);
+            let err = assert_err!(encoder.append(other));
+            assert_eq!(err.kind(), EncodingErrorKind::Other {
+                kind: "appending buffer with different mail type"
+            });
+        }
+
+        #[test]
+        fn len_and_is_empty() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            assert!(encoder.is_empty());
+            assert_eq!(encoder.len(), 0);
+            assert_ok!(encoder.write_header_line(|hdl| {
+                hdl.write_str(SoftAsciiStr::from_unchecked("X: a"))
+            }));
+            encoder.write_blank_line();
+            encoder.write_body_unchecked(&"body\r\n");
+            assert!(!encoder.is_empty());
+            assert_eq!(encoder.len(), 14);
+            assert_eq!(encoder.len(), encoder.as_slice().len());
+        }
+
+        #[test]
+        fn with_buffer_reuses_capacity() {
+            let mut string = String::with_capacity(1024);
+            string.push_str("old content");
+            let ptr = string.as_ptr();
+
+            let mut encoder = EncodingBuffer::with_buffer(MailType::Ascii, string);
+            assert!(encoder.is_empty());
+            assert_ok!(encoder.write_header_line(|hdl| {
+                hdl.write_str(SoftAsciiStr::from_unchecked("X: a"))
+            }));
+
+            let string = assert_ok!(encoder.into_buffer());
+            assert_eq!(string, "X: a\r\n");
+            assert!(string.capacity() >= 1024);
+            assert_eq!(string.as_ptr(), ptr);
+        }
+
+        #[test]
+        fn into_buffer_fails_on_binary_body() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            encoder.write_binary_body_unchecked(&[0xffu8, 0xfe]);
+            let err = assert_err!(encoder.into_buffer());
+            assert_eq!(err.kind(), EncodingErrorKind::InvalidTextEncoding {
+                expected_encoding: UTF_8,
+                got_encoding: UNKNOWN
+            });
+        }
+
+        #[test]
+        fn reset_keeps_allocation() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            assert_ok!(encoder.write_header_line(|hdl| {
+                hdl.write_str(SoftAsciiStr::from_unchecked("X: a"))
+            }));
+            let ptr = encoder.as_slice().as_ptr();
+
+            encoder.reset();
+            assert!(encoder.is_empty());
+            assert!(encoder.trace.is_empty());
+
+            assert_ok!(encoder.write_header_line(|hdl| {
+                hdl.write_str(SoftAsciiStr::from_unchecked("Y: b"))
+            }));
+            assert_eq!(assert_ok!(encoder.as_str()), "Y: b\r\n");
+            assert_eq!(encoder.as_slice().as_ptr(), ptr);
+        }
+
+        #[test]
+        fn reset_after_leaked_handle() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            {
+                let mut handle = encoder.writer();
+                assert_ok!(handle.write_str(SoftAsciiStr::from_unchecked("X: unfinished")));
+                ::std::mem::forget(handle);
+            }
+            assert!(encoder.has_open_handle());
+
+            encoder.reset();
+            assert_not!(encoder.has_open_handle());
+            assert_ok!(encoder.write_header_line(|hdl| {
+                hdl.write_str(SoftAsciiStr::from_unchecked("Y: b"))
+            }));
+            assert_eq!(assert_ok!(encoder.as_str()), "Y: b\r\n");
+        }
+
+        #[test]
+        fn set_mail_type() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            encoder.reset();
+            encoder.set_mail_type(MailType::Internationalized);
+            assert_eq!(encoder.mail_type(), MailType::Internationalized);
+            assert_ok!(encoder.write_header_line(|hdl| {
+                hdl.write_utf8("X: ü")
+            }));
+            assert_eq!(assert_ok!(encoder.as_str()), "X: ü\r\n");
+        }
+
+        #[test]
+        fn write_header_line_measured() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            assert_ok!(encoder.write_header_line(|hdl| {
+                hdl.write_str(SoftAsciiStr::from_unchecked("X: a"))
+            }));
+            let len = assert_ok!(encoder.write_header_line_measured(|hdl| {
+                hdl.write_str(SoftAsciiStr::from_unchecked("Y: "))?;
+                hdl.write_str(SoftAsciiStr::from_unchecked(&"b".repeat(70)))?;
+                hdl.write_fws();
+                hdl.write_str(SoftAsciiStr::from_unchecked(&"c".repeat(10)))
+            }));
+            // "Y: bbb..bbb\r\n ccc..ccc\r\n"
+            assert_eq!(len, 3 + 70 + 2 + 1 + 10 + 2);
+            assert_eq!(encoder.len(), 6 + len);
+        }
+
+        #[test]
+        fn write_header_line_measured_on_error() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            let err = assert_err!(encoder.write_header_line_measured(|hdl| {
+                hdl.write_str(SoftAsciiStr::from_unchecked("X: a"))?;
+                hdl.write_utf8("ü")
+            }));
+            assert_eq!(err.kind(), EncodingErrorKind::InvalidTextEncoding {
+                expected_encoding: US_ASCII,
+                got_encoding: UTF_8
+            });
+            assert!(encoder.is_empty());
+        }
+
+        #[test]
+        fn write_shared_body() {
+            use std::sync::Arc;
+
+            let body: Arc<[u8]> = Arc::from(&b"shared body\r\n"[..]);
+            let mut first = EncodingBuffer::new(MailType::Ascii);
+            let mut second = EncodingBuffer::new(MailType::Ascii);
+
+            first.write_body_unchecked(&body);
+            second.write_binary_body_unchecked(&body.clone());
+
+            assert_eq!(first.as_slice(), b"shared body\r\n");
+            assert_eq!(second.as_slice(), b"shared body\r\n");
+        }
+
+        #[test]
+        fn write_shared_vec_body() {
+            use std::sync::Arc;
+
+            let body = Arc::new(b"shared body\r\n".to_vec());
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            encoder.write_body_unchecked(&**body);
+            assert_eq!(encoder.as_slice(), b"shared body\r\n");
+        }
+
+        #[test]
+        fn normalize_header_folds() {
+            let mut encoder = EncodingBuffer::from((
+                MailType::Ascii,
+                b"X: a\r\n\tb\r\nY: c\r\n  d\r\n \te\r\n\r\nbody\r\n\tx\r\n".to_vec()
+            ));
+            encoder.normalize_header_folds();
+            assert_eq!(
+                assert_ok!(encoder.as_str()),
+                "X: a\r\n b\r\nY: c\r\n d\r\n e\r\n\r\nbody\r\n\tx\r\n"
+            );
+        }
+
+        #[test]
+        fn normalize_header_folds_without_body() {
+            let mut encoder = EncodingBuffer::from((
+                MailType::Ascii,
+                b"X: a\r\n\t\tb\r\n".to_vec()
+            ));
+            encoder.normalize_header_folds();
+            assert_eq!(assert_ok!(encoder.as_str()), "X: a\r\n b\r\n");
+        }
+
+        #[test]
+        fn normalize_header_folds_keeps_quoted_strings() {
+            let mut encoder = EncodingBuffer::from((
+                MailType::Ascii,
+                b"X: \"a \\\"\r\n  b\"\r\n  c\r\nY: \"d\r\n\t\te\r\n\r\n".to_vec()
+            ));
+            encoder.normalize_header_folds();
+            assert_eq!(
+                assert_ok!(encoder.as_str()),
+                "X: \"a \\\"\r\n  b\"\r\n c\r\nY: \"d\r\n\t\te\r\n\r\n"
+            );
+        }
+
+        #[test]
+        fn normalize_header_folds_ignores_quotes_in_comments() {
+            let mut encoder = EncodingBuffer::from((
+                MailType::Ascii,
+                b"X: (a \" (b \\)) \")\r\n  c \"d\r\n  e\"\r\n  f\r\n\r\n".to_vec()
+            ));
+            encoder.normalize_header_folds();
+            assert_eq!(
+                assert_ok!(encoder.as_str()),
+                "X: (a \" (b \\)) \")\r\n c \"d\r\n  e\"\r\n f\r\n\r\n"
+            );
+        }
+    }
+
+
+    mod EncodingWriter {
+        #![allow(non_snake_case)]
+        use std::mem;
+
+        use ::error::{EncodingError, US_ASCII, UTF_8, UNKNOWN};
+        use super::*;
+        use super::{ _Encoder as EncodingBuffer };
+        use super::super::NulPolicy;
+
+        #[test]
+        fn commit_partial_and_drop_does_not_panic() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            {
+                let mut handle = encoder.writer();
+                assert_ok!(handle.write_str(SoftAsciiStr::from_unchecked("12")));
+                handle.commit_partial_header();
+            }
+            assert_eq!(encoder.as_slice(), b"12");
+        }
+
+        #[test]
+        fn undo_does_undo() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            {
                 let mut handle = encoder.writer();
                 assert_ok!(
                     handle.write_str(SoftAsciiStr::from_unchecked("Header-One: 12")));
@@ -1066,6 +2739,30 @@ mod test {
             }
         }
 
+        #[test]
+        fn orphan_cr_error_reports_column() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            {
+                let mut handle = encoder.writer();
+                let err = assert_err!(handle.write_str(SoftAsciiStr::from_str("H: \ra").unwrap()));
+                assert_eq!(err.kind(), EncodingErrorKind::Malformed);
+                assert_eq!(err.str_context(), Some("at column 3"));
+                handle.undo_header()
+            }
+        }
+
+        #[test]
+        fn current_column_counts_chars() {
+            let mut encoder = EncodingBuffer::new(MailType::Internationalized);
+            {
+                let mut handle = encoder.writer();
+                assert_ok!(handle.write_utf8("X: a\r\n ä€b"));
+                assert_eq!(handle.current_column(), 4);
+                assert_eq!(handle.current_line_byte_length(), 7);
+                handle.undo_header()
+            }
+        }
+
         #[test]
         fn orphan_trailing_lf() {
             let mut encoder = EncodingBuffer::new(MailType::Ascii);
@@ -1263,6 +2960,41 @@ mod test {
             }
         }
 
+        #[test]
+        fn hard_line_limit_applies_to_current_line() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            {
+                let mut handle = encoder.writer();
+                assert_ok!(handle.write_str(SoftAsciiStr::from_unchecked("X: y")));
+                handle.finish_header();
+                for x in 0..998 {
+                    if let Err(_) = handle.write_char(SoftAsciiChar::from_unchecked('X')) {
+                        panic!("error when writing char nr.: {:?}", x+1)
+                    }
+                }
+                assert_err!(handle.write_char(SoftAsciiChar::from_unchecked('X')));
+                handle.undo_header();
+            }
+        }
+
+        #[test]
+        fn override_soft_limit_applies_to_current_header() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            {
+                let mut handle = encoder.writer();
+                handle.override_soft_limit(10);
+                assert_ok!(handle.write_str(SoftAsciiStr::from_unchecked("X: 12345")));
+                handle.write_fws();
+                assert_ok!(handle.write_str(SoftAsciiStr::from_unchecked("678")));
+                handle.finish_header();
+                assert_ok!(handle.write_str(SoftAsciiStr::from_unchecked("Y: 12345")));
+                handle.write_fws();
+                assert_ok!(handle.write_str(SoftAsciiStr::from_unchecked("678")));
+                handle.finish_header();
+            }
+            assert_eq!(encoder.as_slice(), b"X: 12345\r\n 678\r\nY: 12345 678\r\n");
+        }
+
         #[test]
         fn write_utf8_fail_on_ascii_mail() {
             let mut encoder = EncodingBuffer::new(MailType::Ascii);
@@ -1288,10 +3020,10 @@ mod test {
             let mut encoder = EncodingBuffer::new(MailType::Internationalized);
             {
                 let mut handle = encoder.writer();
-                assert_ok!(handle.write_utf8("❤"));
+                assert_ok!(handle.write_utf8("X: ❤"));
                 handle.finish_header();
             }
-            assert_eq!(encoder.as_str().unwrap(), "❤\r\n");
+            assert_eq!(encoder.as_str().unwrap(), "X: ❤\r\n");
         }
 
         #[test]
@@ -1299,6 +3031,7 @@ mod test {
             let mut encoder = EncodingBuffer::new(MailType::Ascii);
             {
                 let mut handle = encoder.writer();
+                assert_ok!(handle.write_str(SoftAsciiStr::from_unchecked("X: ")));
                 assert_ok!(handle.write_if_atext("hoho")
                     .handle_condition_failure(|_|panic!("no condition failur expected")));
                 let mut had_cond_failure = false;
@@ -1309,7 +3042,7 @@ mod test {
                     .handle_condition_failure(|_|panic!("no condition failur expected")));
                 handle.finish_header();
             }
-            assert_eq!(encoder.as_slice(), b"hoho\r\n");
+            assert_eq!(encoder.as_slice(), b"X: hoho\r\n");
         }
 
         #[test]
@@ -1317,6 +3050,7 @@ mod test {
             let mut encoder = EncodingBuffer::new(MailType::Internationalized);
             {
                 let mut handle = encoder.writer();
+                assert_ok!(handle.write_str(SoftAsciiStr::from_unchecked("X: ")));
                 assert_ok!(handle.write_if_atext("hoho")
                     .handle_condition_failure(|_|panic!("no condition failur expected")));
                 let mut had_cond_failure = false;
@@ -1327,7 +3061,49 @@ mod test {
                     .handle_condition_failure(|_|panic!("no condition failur expected")));
                 handle.finish_header();
             }
-            assert_eq!(encoder.as_str().unwrap(), "hoho❤\r\n");
+            assert_eq!(encoder.as_str().unwrap(), "X: hoho❤\r\n");
+        }
+
+        #[test]
+        fn try_write_quotable() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            {
+                let mut handle = encoder.writer();
+                assert_ok!(handle.write_str(SoftAsciiStr::from_unchecked("X: ")));
+                assert_ok!(handle.write_if_quotable("John Doe")
+                    .handle_condition_failure(|_|panic!("no condition failur expected")));
+                handle.write_fws();
+                let name = "John Q. Doe";
+                assert_ok!(handle.write_if_quotable(name)
+                    .handle_condition_failure(|handle| handle.write_quoted_string(name)));
+                handle.write_fws();
+                assert_ok!(handle.write_if_quotable("")
+                    .handle_condition_failure(|handle| handle.write_quoted_string("")));
+                handle.finish_header();
+            }
+            assert_eq!(encoder.as_slice(), &b"X: John Doe \"John Q. Doe\" \"\"\r\n"[..]);
+        }
+
+        #[test]
+        fn fold_positions_match_inserted_line_breaks() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            {
+                let mut handle = encoder.writer();
+                assert_ok!(handle.write_str(SoftAsciiStr::from_unchecked("X-Words:")));
+                for _ in 0..30 {
+                    handle.write_fws();
+                    assert_ok!(handle.write_str(SoftAsciiStr::from_unchecked("abcdefghij")));
+                }
+                let positions = handle.fold_positions();
+                let line_breaks = handle.snapshot_pending()
+                    .match_indices("\r\n")
+                    .map(|(idx, _)| idx)
+                    .collect::<Vec<_>>();
+                assert_eq!(positions.len(), 4);
+                assert_eq!(positions, line_breaks);
+                handle.finish_header();
+                assert!(handle.fold_positions().is_empty());
+            }
         }
 
         #[test]
@@ -1335,6 +3111,7 @@ mod test {
             let mut encoder = EncodingBuffer::new(MailType::Internationalized);
             {
                 let mut handle = encoder.writer();
+                assert_ok!(handle.write_str(SoftAsciiStr::from_unchecked("X: ")));
                 assert_ok!(handle.write_if_atext("hoho")
                     .handle_condition_failure(|_|panic!("no condition failur expected")));
                 let mut had_cond_failure = false;
@@ -1348,7 +3125,7 @@ mod test {
                 handle.finish_header();
                 handle.finish_header();
             }
-            assert_eq!(encoder.as_str().unwrap(), "hoho❤\r\n");
+            assert_eq!(encoder.as_str().unwrap(), "X: hoho❤\r\n");
         }
 
         #[test]
@@ -1377,7 +3154,7 @@ mod test {
             encoder.write_body_unchecked(&"da body");
             {
                 let mut handle = encoder.writer();
-                assert_ok!(handle.write_utf8("❤"));
+                assert_ok!(handle.write_utf8("X: ❤"));
                 handle.finish_header();
             }
             assert_eq!(
@@ -1385,7 +3162,7 @@ mod test {
                 concat!(
                     "H: yay\r\n",
                     "da body\r\n",
-                    "❤\r\n"
+                    "X: ❤\r\n"
                 ).as_bytes()
             );
         }
@@ -1587,6 +3364,8 @@ mod test {
             );
             let mut encoder = EncodingBuffer::new(MailType::Internationalized);
             let res = encoder.write_header_line(|hdl| {
+                // start a new line so that the fws are at the start of a line
+                hdl.write_utf8("X: a\r\n")?;
                 hdl.write_fws();
                 hdl.write_fws();
                 hdl.write_utf8(long_line)?;
@@ -1594,23 +3373,630 @@ mod test {
             });
             assert_ok!(res);
             assert_eq!(encoder.trace, vec![
+                NowUtf8, Text("X: a".to_owned()),
+                CRLF,
                 MarkFWS, NowChar, Text(" ".to_owned()),
                 MarkFWS, NowChar, Text(" ".to_owned()),
                 NowUtf8, Text(long_line.to_owned()),
                 CRLF,
                 End
             ]);
-            assert_eq!(encoder.as_slice(), format!("  {}\r\n", long_line).as_bytes())
+            assert_eq!(encoder.as_slice(), format!("X: a\r\n  {}\r\n", long_line).as_bytes())
         }
-    }
 
-    ec_test! {
-        does_ec_test_work,
-        {
-            use super::EncodingWriter;
-            enc_func!(|x: &mut EncodingWriter| {
-                x.write_utf8("hy")
-            })
+        #[test]
+        fn write_ipv4_domain_literal() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            assert_ok!(encoder.write_header_line(|hdl| {
+                hdl.write_str(SoftAsciiStr::from_unchecked("X: "))?;
+                hdl.write_domain_literal("192.0.2.1")
+            }));
+            assert_eq!(encoder.as_slice(), b"X: [192.0.2.1]\r\n");
+        }
+
+        #[test]
+        fn write_ipv6_domain_literal() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            assert_ok!(encoder.write_header_line(|hdl| {
+                hdl.write_str(SoftAsciiStr::from_unchecked("X: "))?;
+                hdl.write_domain_literal("IPv6:2001:db8::1")
+            }));
+            assert_eq!(encoder.as_slice(), b"X: [IPv6:2001:db8::1]\r\n");
+        }
+
+        #[test]
+        fn write_domain_literal_with_illegal_char() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            let err = assert_err!(encoder.write_header_line(|hdl| {
+                hdl.write_str(SoftAsciiStr::from_unchecked("X: "))?;
+                hdl.write_domain_literal("192.0.2.1]")
+            }));
+            assert_eq!(err.kind(), EncodingErrorKind::Malformed);
+            assert_eq!(encoder.as_slice(), b"");
+        }
+
+        #[test]
+        fn write_param_value_as_token() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            assert_ok!(encoder.write_header_line(|hdl| {
+                hdl.write_str(SoftAsciiStr::from_unchecked("X: charset="))?;
+                hdl.write_param_value("utf-8")
+            }));
+            assert_eq!(encoder.as_slice(), b"X: charset=utf-8\r\n");
+        }
+
+        #[test]
+        fn write_param_value_quoted() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            assert_ok!(encoder.write_header_line(|hdl| {
+                hdl.write_str(SoftAsciiStr::from_unchecked("X: boundary="))?;
+                hdl.write_param_value("a b")
+            }));
+            assert_eq!(encoder.as_slice(), b"X: boundary=\"a b\"\r\n");
+        }
+
+        #[test]
+        fn write_param_value_quoted_and_escaped() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            assert_ok!(encoder.write_header_line(|hdl| {
+                hdl.write_str(SoftAsciiStr::from_unchecked("X: name="))?;
+                hdl.write_param_value("a\"b")
+            }));
+            assert_eq!(encoder.as_slice(), b"X: name=\"a\\\"b\"\r\n");
+        }
+
+        #[test]
+        fn write_foldable_token_folds_long_token() {
+            let token = "aB3+/".repeat(60);
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            assert_ok!(encoder.write_header_line(|hdl| {
+                hdl.write_str(SoftAsciiStr::from_unchecked("X-Sig: b="))?;
+                hdl.write_foldable_token(SoftAsciiStr::from_unchecked(&token))
+            }));
+
+            let written = assert_ok!(encoder.as_str());
+            let lines = written.split("\r\n").collect::<Vec<_>>();
+            assert!(lines.len() > 2);
+            for line in lines {
+                assert!(line.len() <= super::super::LINE_LEN_SOFT_LIMIT);
+            }
+            assert_eq!(
+                written.replace("\r\n ", ""),
+                format!("X-Sig: b={}\r\n", token)
+            );
+        }
+
+        #[test]
+        fn fold_at_tab_keeps_tab_by_default() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            assert_ok!(encoder.write_header_line(|hdl| {
+                hdl.write_str(SoftAsciiStr::from_unchecked("X: "))?;
+                hdl.write_str(SoftAsciiStr::from_unchecked(&"a".repeat(40)))?;
+                hdl.mark_fws_pos();
+                hdl.write_str(SoftAsciiStr::from_unchecked("\t"))?;
+                hdl.write_str(SoftAsciiStr::from_unchecked(&"b".repeat(40)))
+            }));
+            assert_eq!(
+                assert_ok!(encoder.as_str()),
+                format!("X: {}\r\n\t{}\r\n", "a".repeat(40), "b".repeat(40))
+            );
+        }
+
+        #[test]
+        fn fold_at_tab_with_normalize_fold_whitespace() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            encoder.set_normalize_fold_whitespace(true);
+            assert_ok!(encoder.write_header_line(|hdl| {
+                hdl.write_str(SoftAsciiStr::from_unchecked("X: "))?;
+                hdl.write_str(SoftAsciiStr::from_unchecked(&"a".repeat(40)))?;
+                hdl.mark_fws_pos();
+                hdl.write_str(SoftAsciiStr::from_unchecked("\t"))?;
+                hdl.write_str(SoftAsciiStr::from_unchecked(&"b".repeat(40)))
+            }));
+            assert_eq!(
+                assert_ok!(encoder.as_str()),
+                format!("X: {}\r\n {}\r\n", "a".repeat(40), "b".repeat(40))
+            );
+        }
+
+        #[test]
+        fn normalize_fold_whitespace_keeps_tab_in_quoted_string() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            encoder.set_normalize_fold_whitespace(true);
+            assert_ok!(encoder.write_header_line(|hdl| {
+                hdl.write_str(SoftAsciiStr::from_unchecked("X: "))?;
+                hdl.write_quoted_string(&format!("{}\t{}", "a".repeat(60), "b".repeat(20)))
+            }));
+            assert_eq!(
+                assert_ok!(encoder.as_str()),
+                format!("X: \"{}\r\n\t{}\"\r\n", "a".repeat(60), "b".repeat(20))
+            );
+        }
+
+        #[cfg(debug_assertions)]
+        #[test]
+        #[should_panic]
+        fn finish_header_without_colon_panics() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            let mut handle = encoder.writer();
+            assert_ok!(handle.write_str(SoftAsciiStr::from_unchecked("X-No-Colon value")));
+            handle.finish_header();
+        }
+
+        #[test]
+        fn soft_limit_override_is_reset_on_finish() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            {
+                let mut handle = encoder.writer();
+                assert_eq!(handle.soft_limit(), 78);
+                assert_eq!(handle.hard_limit(), 998);
+
+                handle.override_soft_limit(10);
+                assert_eq!(handle.soft_limit(), 10);
+                assert_ok!(handle.write_str(SoftAsciiStr::from_unchecked("X: 12345")));
+                handle.write_fws();
+                assert_ok!(handle.write_str(SoftAsciiStr::from_unchecked("678")));
+                handle.finish_header();
+
+                assert_eq!(handle.soft_limit(), 78);
+                assert_eq!(handle.hard_limit(), 998);
+            }
+            assert_eq!(encoder.as_slice(), b"X: 12345\r\n 678\r\n");
+        }
+
+        #[test]
+        fn nul_policy_error() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            let mut handle = encoder.writer();
+            let err = assert_err!(handle.apply_nul_policy("X: a\0b"));
+            assert_eq!(err.kind(), EncodingErrorKind::Malformed);
+            handle.undo_header();
+        }
+
+        #[test]
+        fn nul_policy_strip() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            encoder.set_nul_policy(NulPolicy::Strip);
+            let mut handle = encoder.writer();
+            assert_eq!(assert_ok!(handle.apply_nul_policy("X: a\0b")), "X: ab");
+            handle.undo_header();
+        }
+
+        #[test]
+        fn nul_policy_replace() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            encoder.set_nul_policy(NulPolicy::Replace('?'));
+            let mut handle = encoder.writer();
+            assert_eq!(assert_ok!(handle.apply_nul_policy("X: a\0b")), "X: a?b");
+            handle.undo_header();
+        }
+
+        #[test]
+        fn write_words_folds_long_sequence() {
+            let words = vec!["abcdefghij"; 20];
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            assert_ok!(encoder.write_header_line(|hdl| {
+                hdl.write_str(SoftAsciiStr::from_unchecked("X:"))?;
+                hdl.write_fws();
+                hdl.write_words(words.iter().cloned())
+            }));
+
+            let written = assert_ok!(encoder.as_str());
+            let lines = written.split("\r\n").collect::<Vec<_>>();
+            assert!(lines.len() > 2);
+            for line in lines {
+                assert!(line.len() <= super::super::LINE_LEN_SOFT_LIMIT);
+            }
+            assert_eq!(
+                written.replace("\r\n", ""),
+                format!("X: {}", words.join(" "))
+            );
+        }
+
+        #[test]
+        fn fold_after_double_fws() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            assert_ok!(encoder.write_header_line(|hdl| {
+                hdl.write_str(SoftAsciiStr::from_unchecked("X:"))?;
+                hdl.write_fws();
+                hdl.write_fws();
+                hdl.write_str(SoftAsciiStr::from_unchecked(&"a".repeat(80)))
+            }));
+            assert_eq!(
+                assert_ok!(encoder.as_str()),
+                format!("X: \r\n {}\r\n", "a".repeat(80))
+            );
+        }
+
+        #[test]
+        fn fold_after_double_fws_collapsing_ws() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            encoder.set_collapse_folding_ws(true);
+            assert_ok!(encoder.write_header_line(|hdl| {
+                hdl.write_str(SoftAsciiStr::from_unchecked("X:"))?;
+                hdl.write_fws();
+                hdl.write_fws();
+                hdl.write_str(SoftAsciiStr::from_unchecked(&"a".repeat(80)))
+            }));
+            assert_eq!(
+                assert_ok!(encoder.as_str()),
+                format!("X:\r\n {}\r\n", "a".repeat(80))
+            );
+        }
+
+        #[test]
+        fn fold_on_existing_space() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            assert_ok!(encoder.write_header_line(|hdl| {
+                hdl.write_str(SoftAsciiStr::from_unchecked("X: "))?;
+                hdl.write_str(SoftAsciiStr::from_unchecked(&"a".repeat(40)))?;
+                hdl.write_fws();
+                hdl.write_fws();
+                hdl.write_fws();
+                hdl.write_str(SoftAsciiStr::from_unchecked(&"b".repeat(40)))
+            }));
+            assert_eq!(
+                assert_ok!(encoder.as_str()),
+                format!("X: {}  \r\n {}\r\n", "a".repeat(40), "b".repeat(40))
+            );
+        }
+
+        #[test]
+        fn fold_on_existing_space_collapsing_ws() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            encoder.set_collapse_folding_ws(true);
+            assert_ok!(encoder.write_header_line(|hdl| {
+                hdl.write_str(SoftAsciiStr::from_unchecked("X: "))?;
+                hdl.write_str(SoftAsciiStr::from_unchecked(&"a".repeat(40)))?;
+                hdl.write_fws();
+                hdl.write_fws();
+                hdl.write_fws();
+                hdl.write_str(SoftAsciiStr::from_unchecked(&"b".repeat(40)))
+            }));
+            assert_eq!(
+                assert_ok!(encoder.as_str()),
+                format!("X: {}\r\n {}\r\n", "a".repeat(40), "b".repeat(40))
+            );
+        }
+
+        #[test]
+        fn fold_triggered_by_fws_space() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            assert_ok!(encoder.write_header_line(|hdl| {
+                hdl.write_str(SoftAsciiStr::from_unchecked("X: "))?;
+                hdl.write_str(SoftAsciiStr::from_unchecked(&"a".repeat(75)))?;
+                hdl.write_fws();
+                hdl.write_str(SoftAsciiStr::from_unchecked("b"))
+            }));
+            assert_eq!(
+                assert_ok!(encoder.as_str()),
+                format!("X: {}\r\n b\r\n", "a".repeat(75))
+            );
+        }
+
+        #[test]
+        fn collapsing_ws_keeps_ws_in_quoted_string() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            encoder.set_collapse_folding_ws(true);
+            let text = format!("{}  {}", "a".repeat(60), "b".repeat(20));
+            assert_ok!(encoder.write_header_line(|hdl| {
+                hdl.write_str(SoftAsciiStr::from_unchecked("X:"))?;
+                hdl.write_fws();
+                hdl.write_quoted_string(&text)
+            }));
+            let written = assert_ok!(encoder.as_str());
+            assert_eq!(
+                written,
+                format!("X: \"{} \r\n {}\"\r\n", "a".repeat(60), "b".repeat(20))
+            );
+            assert_eq!(written.replace("\r\n ", " "), format!("X: \"{}\"\r\n", text));
+        }
+
+        #[test]
+        fn remaining_budget_after_fold() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            assert_ok!(encoder.write_header_line(|hdl| {
+                hdl.write_str(SoftAsciiStr::from_unchecked("X: "))?;
+                assert_eq!(hdl.remaining_soft_budget(), 75);
+                assert_eq!(hdl.remaining_hard_budget(), 995);
+                hdl.write_str(SoftAsciiStr::from_unchecked(&"a".repeat(70)))?;
+                hdl.write_fws();
+                hdl.write_str(SoftAsciiStr::from_unchecked(&"b".repeat(10)))?;
+                // the line was broken at the fws, so it is " bbbbbbbbbb"
+                assert_eq!(hdl.current_line_byte_length(), 11);
+                assert_eq!(hdl.remaining_soft_budget(), 67);
+                assert_eq!(hdl.remaining_hard_budget(), 987);
+                Ok(())
+            }));
+        }
+
+        #[test]
+        fn remaining_soft_budget_saturates() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            assert_ok!(encoder.write_header_line(|hdl| {
+                hdl.write_str(SoftAsciiStr::from_unchecked("X: "))?;
+                hdl.write_str(SoftAsciiStr::from_unchecked(&"a".repeat(80)))?;
+                assert_eq!(hdl.remaining_soft_budget(), 0);
+                assert_eq!(hdl.remaining_hard_budget(), 998 - 83);
+                Ok(())
+            }));
+        }
+
+        #[test]
+        fn rollback_to_savepoint() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            assert_ok!(encoder.write_header_line(|hdl| {
+                hdl.write_str(SoftAsciiStr::from_unchecked("X: a"))?;
+                let savepoint = hdl.savepoint();
+                hdl.write_str(SoftAsciiStr::from_unchecked(", b"))?;
+                hdl.rollback(savepoint);
+                hdl.write_str(SoftAsciiStr::from_unchecked(", c"))
+            }));
+            assert_eq!(assert_ok!(encoder.as_str()), "X: a, c\r\n");
+        }
+
+        #[test]
+        fn nested_savepoints() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            assert_ok!(encoder.write_header_line(|hdl| {
+                hdl.write_str(SoftAsciiStr::from_unchecked("X: a"))?;
+                let outer = hdl.savepoint();
+                hdl.write_str(SoftAsciiStr::from_unchecked(", b"))?;
+                let inner = hdl.savepoint();
+                hdl.write_str(SoftAsciiStr::from_unchecked(", c"))?;
+                hdl.rollback(inner);
+                assert_eq!(hdl.snapshot_pending(), "X: a, b");
+                hdl.rollback(outer);
+                assert_eq!(hdl.snapshot_pending(), "X: a");
+                Ok(())
+            }));
+            assert_eq!(assert_ok!(encoder.as_str()), "X: a\r\n");
+        }
+
+        #[test]
+        fn rollback_restores_line_after_fold() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            assert_ok!(encoder.write_header_line(|hdl| {
+                hdl.write_str(SoftAsciiStr::from_unchecked("X: "))?;
+                hdl.write_str(SoftAsciiStr::from_unchecked(&"a".repeat(70)))?;
+                let savepoint = hdl.savepoint();
+                hdl.write_fws();
+                hdl.write_str(SoftAsciiStr::from_unchecked(&"b".repeat(10)))?;
+                assert_eq!(hdl.current_line_byte_length(), 11);
+                hdl.rollback(savepoint);
+                assert_eq!(hdl.current_line_byte_length(), 73);
+                hdl.write_str(SoftAsciiStr::from_unchecked("c"))
+            }));
+            assert_eq!(
+                assert_ok!(encoder.as_str()),
+                format!("X: {}c\r\n", "a".repeat(70))
+            );
+        }
+
+        #[test]
+        fn rollback_undoes_fold_at_fws_before_savepoint() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            assert_ok!(encoder.write_header_line(|hdl| {
+                hdl.write_str(SoftAsciiStr::from_unchecked("X: aaaa"))?;
+                hdl.write_fws();
+                hdl.write_str(SoftAsciiStr::from_unchecked("bbb"))?;
+                let savepoint = hdl.savepoint();
+                hdl.write_str(SoftAsciiStr::from_unchecked(&"c".repeat(80)))?;
+                assert!(hdl.snapshot_pending().starts_with("X: aaaa\r\n bbbc"));
+                hdl.rollback(savepoint);
+                assert_eq!(hdl.current_line_byte_length(), 11);
+                Ok(())
+            }));
+            assert_eq!(assert_ok!(encoder.as_str()), "X: aaaa bbb\r\n");
+        }
+
+        #[test]
+        #[should_panic]
+        fn rollback_to_stale_savepoint_panics() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            let mut handle = encoder.writer();
+            assert_ok!(handle.write_str(SoftAsciiStr::from_unchecked("X: a")));
+            let outer = handle.savepoint();
+            assert_ok!(handle.write_str(SoftAsciiStr::from_unchecked(", b")));
+            let inner = handle.savepoint();
+            handle.rollback(outer);
+            assert_ok!(handle.write_str(SoftAsciiStr::from_unchecked(", c, d")));
+            handle.rollback(inner);
+        }
+
+        #[test]
+        #[should_panic]
+        fn rollback_after_finish_header_panics() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            let mut handle = encoder.writer();
+            assert_ok!(handle.write_str(SoftAsciiStr::from_unchecked("X: a")));
+            let savepoint = handle.savepoint();
+            handle.finish_header();
+            assert_ok!(handle.write_str(SoftAsciiStr::from_unchecked("Y: b")));
+            handle.rollback(savepoint);
+        }
+
+        #[test]
+        fn write_soft_ascii_bytes() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            assert_ok!(encoder.write_header_line(|hdl| {
+                hdl.write_soft_ascii_bytes(b"X: ")?;
+                hdl.write_soft_ascii_bytes("a".repeat(70).as_bytes())?;
+                hdl.write_fws();
+                hdl.write_soft_ascii_bytes(b"bbbbbbbbbb")
+            }));
+            assert_eq!(
+                assert_ok!(encoder.as_str()),
+                format!("X: {}\r\n bbbbbbbbbb\r\n", "a".repeat(70))
+            );
+        }
+
+        #[test]
+        fn write_soft_ascii_bytes_rejects_orphan_cr_lf() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            let mut handle = encoder.writer();
+            let err = assert_err!(handle.write_soft_ascii_bytes(b"X: a\rb"));
+            assert_eq!(err.kind(), EncodingErrorKind::Malformed);
+            handle.undo_header();
+            let err = assert_err!(handle.write_soft_ascii_bytes(b"X: a\nb"));
+            assert_eq!(err.kind(), EncodingErrorKind::Malformed);
+            handle.undo_header();
+        }
+
+        #[test]
+        fn write_soft_ascii_bytes_rejects_non_ascii() {
+            let mut encoder = EncodingBuffer::new(MailType::Internationalized);
+            let mut handle = encoder.writer();
+            assert_ok!(handle.write_soft_ascii_bytes(b"X: "));
+            let err = assert_err!(handle.write_soft_ascii_bytes(b"a\xe9b"));
+            assert_eq!(err.kind(), EncodingErrorKind::InvalidTextEncoding {
+                expected_encoding: US_ASCII,
+                got_encoding: UNKNOWN
+            });
+            assert_eq!(handle.snapshot_pending(), "X: ");
+            handle.undo_header();
+        }
+
+        #[test]
+        fn write_char_iter() {
+            let mut encoder = EncodingBuffer::new(MailType::Internationalized);
+            assert_ok!(encoder.write_header_line(|hdl| {
+                hdl.write_str(SoftAsciiStr::from_unchecked("X: "))?;
+                hdl.write_char_iter("grüße".chars().flat_map(|ch| ch.to_uppercase()))
+            }));
+            assert_eq!(assert_ok!(encoder.as_str()), "X: GRÜSSE\r\n");
+        }
+
+        #[test]
+        fn write_char_iter_rejects_non_ascii_in_ascii_mail() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            let mut handle = encoder.writer();
+            assert_ok!(handle.write_str(SoftAsciiStr::from_unchecked("X: ")));
+            assert_ok!(handle.write_char_iter("abc".chars()));
+            let err = assert_err!(handle.write_char_iter("dü".chars()));
+            assert_eq!(err.kind(), EncodingErrorKind::InvalidTextEncoding {
+                expected_encoding: US_ASCII,
+                got_encoding: UTF_8
+            });
+            assert_eq!(err.str_context(), Some("at column 7"));
+            handle.undo_header();
+        }
+
+        #[test]
+        fn write_quoted_string_folds_long_phrase() {
+            let words = vec!["abcdefghi"; 10];
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            assert_ok!(encoder.write_header_line(|hdl| {
+                hdl.write_str(SoftAsciiStr::from_unchecked("X: "))?;
+                hdl.write_quoted_string(&words.join(" "))
+            }));
+            assert_eq!(
+                assert_ok!(encoder.as_str()),
+                format!("X: \"{}\r\n {}\"\r\n", words[..7].join(" "), words[7..].join(" "))
+            );
+        }
+
+        #[test]
+        fn write_quoted_string_does_not_write_on_error() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            let mut handle = encoder.writer();
+            assert_ok!(handle.write_str(SoftAsciiStr::from_unchecked("X: ")));
+            let err = assert_err!(handle.write_quoted_string("a b\r\nc"));
+            assert_eq!(err.kind(), EncodingErrorKind::Malformed);
+            assert_eq!(handle.snapshot_pending(), "X: ");
+            handle.undo_header();
+        }
+
+        #[test]
+        fn long_received_header_folds_at_default_soft_limit() {
+            let words = vec!["abcdefghi"; 30];
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            assert_ok!(encoder.write_header_line(|hdl| {
+                hdl.write_str(SoftAsciiStr::from_unchecked("Received:"))?;
+                hdl.write_fws();
+                hdl.write_words(words.iter().cloned())
+            }));
+            assert_eq!(assert_ok!(encoder.as_str()).matches("\r\n").count(), 5);
+        }
+
+        #[test]
+        fn raising_soft_limit_reduces_folds() {
+            let words = vec!["abcdefghi"; 30];
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            assert_ok!(encoder.write_header_line(|hdl| {
+                hdl.override_soft_limit(900);
+                hdl.write_str(SoftAsciiStr::from_unchecked("Received:"))?;
+                hdl.write_fws();
+                hdl.write_words(words.iter().cloned())
+            }));
+            assert_eq!(assert_ok!(encoder.as_str()).matches("\r\n").count(), 1);
+        }
+
+        #[test]
+        fn can_write_char() {
+            let mut ascii = EncodingBuffer::new(MailType::Ascii);
+            let mut internationalized = EncodingBuffer::new(MailType::Internationalized);
+            let ascii_handle = ascii.writer();
+            let internationalized_handle = internationalized.writer();
+
+            assert!(ascii_handle.can_write_char('a'));
+            assert!(internationalized_handle.can_write_char('a'));
+            assert!(!ascii_handle.can_write_char('↓'));
+            assert!(internationalized_handle.can_write_char('↓'));
+            assert!(!ascii_handle.can_write_char('\n'));
+            assert!(!internationalized_handle.can_write_char('\n'));
+            assert!(ascii_handle.can_write_char(' '));
+            assert!(!ascii_handle.has_unfinished_parts());
+        }
+
+        #[test]
+        fn write_str_unchecked_rejects_header_injection() {
+            let mut encoder = EncodingBuffer::new(MailType::Ascii);
+            let mut handle = encoder.writer();
+            assert_ok!(handle.write_str(SoftAsciiStr::from_unchecked("Subject: ")));
+            let err = assert_err!(handle.write_str_unchecked("x\r\nBcc: evil@x"));
+            assert_eq!(err.kind(), EncodingErrorKind::HeaderInjection);
+            assert_eq!(err.str_context(), Some("x\r\nBcc: evil@x"));
+            assert_eq!(handle.snapshot_pending(), "Subject: ");
+            handle.undo_header();
+        }
+
+        #[test]
+        fn write_str_and_write_utf8_reject_header_injection() {
+            let mut encoder = EncodingBuffer::new(MailType::Internationalized);
+            let mut handle = encoder.writer();
+            assert_ok!(handle.write_str(SoftAsciiStr::from_unchecked("Subject: ")));
+            let err = assert_err!(handle.write_str(SoftAsciiStr::from_unchecked("x\r\nBcc: evil@x")));
+            assert_eq!(err.kind(), EncodingErrorKind::HeaderInjection);
+            let err = assert_err!(handle.write_utf8("x\r\n\r\nevil body"));
+            assert_eq!(err.kind(), EncodingErrorKind::HeaderInjection);
+            assert_eq!(handle.snapshot_pending(), "Subject: ");
+            handle.undo_header();
+        }
+
+        #[test]
+        fn header_injection_split_across_writes_is_rejected() {
+            let mut encoder = EncodingBuffer::new(MailType::Internationalized);
+            {
+                let mut handle = encoder.writer();
+                assert_ok!(handle.write_utf8("Subject: x\r\n"));
+                let err = assert_err!(handle.write_utf8("Bcc: evil@x"));
+                assert_eq!(err.kind(), EncodingErrorKind::HeaderInjection);
+                let err = assert_err!(handle.write_str_unchecked("\r\n"));
+                assert_eq!(err.kind(), EncodingErrorKind::HeaderInjection);
+                assert_ok!(handle.write_utf8(" folded"));
+                handle.finish_header();
+            }
+            assert_eq!(encoder.as_str().unwrap(), "Subject: x\r\n folded\r\n");
+        }
+
+    ec_test! {
+        does_ec_test_work,
+        {
+            use super::EncodingWriter;
+            enc_func!(|x: &mut EncodingWriter| {
+                x.write_utf8("hy")
+            })
         } => Utf8 => [
             Text "hy"
         ]
@@ -1643,6 +4029,143 @@ mod test {
         ]
     }
 
+    ec_test! {
+        tuple_of_components,
+        {
+            use super::EncodingWriter;
+            (
+                enc_func!(|x: &mut EncodingWriter| {
+                    x.write_str(SoftAsciiStr::from_unchecked("token"))
+                }),
+                enc_func!(|x: &mut EncodingWriter| {
+                    x.write_fws();
+                    x.write_utf8("raw text")
+                })
+            )
+        } => Utf8 => [
+            Text "token",
+            MarkFWS,
+            Text " raw text"
+        ]
+    }
+
+    ec_test! {
+        write_words,
+        {
+            use super::EncodingWriter;
+            enc_func!(|x: &mut EncodingWriter| {
+                x.write_words(vec!["one", "two", "", "three", "four", "five"])
+            })
+        } => Ascii => [
+            Text "one",
+            MarkFWS,
+            Text " two",
+            MarkFWS,
+            Text " three",
+            MarkFWS,
+            Text " four",
+            MarkFWS,
+            Text " five"
+        ]
+    }
+
+    ec_test! {
+        separated_list,
+        {
+            use super::{EncodingWriter, EncodableInHeader, SeparatedList};
+            let items: Vec<Box<EncodableInHeader>> = vec![
+                Box::new(enc_func!(|x: &mut EncodingWriter| {
+                    x.write_str(SoftAsciiStr::from_unchecked("a"))
+                })),
+                Box::new(enc_func!(|x: &mut EncodingWriter| {
+                    x.write_str(SoftAsciiStr::from_unchecked("b"))
+                })),
+                Box::new(enc_func!(|x: &mut EncodingWriter| {
+                    x.write_str(SoftAsciiStr::from_unchecked("c"))
+                }))
+            ];
+            let separator = enc_func!(|x: &mut EncodingWriter| {
+                x.write_str(SoftAsciiStr::from_unchecked(", "))
+            });
+            SeparatedList::new(items, Box::new(separator))
+        } => Ascii => [
+            Text "a",
+            MarkFWS,
+            Text ", ",
+            MarkFWS,
+            Text "b",
+            MarkFWS,
+            Text ", ",
+            MarkFWS,
+            Text "c"
+        ]
+    }
+
+    ec_test! {
+        map_encode_transforms_output,
+        {
+            use std::borrow::Cow;
+            use super::{EncodingWriter, MapEncode};
+            fn uppercase(text: &str) -> Cow<str> {
+                Cow::Owned(text.to_uppercase())
+            }
+            MapEncode::new(
+                enc_func!(|x: &mut EncodingWriter| {
+                    x.write_str(SoftAsciiStr::from_unchecked("hy ho"))
+                }),
+                uppercase
+            )
+        } => Ascii => [
+            Text "HY HO"
+        ]
+    }
+
+    ec_test! {
+        quoted_string_escapes_quotes_and_backslashes,
+        {
+            use super::EncodingWriter;
+            enc_func!(|x: &mut EncodingWriter| {
+                x.write_quoted_string("She said \"hi\"\\path")
+            })
+        } => Ascii => [
+            Text "\"She",
+            MarkFWS,
+            Text " said",
+            MarkFWS,
+            Text " \\\"hi\\\"\\\\path\""
+        ]
+    }
+
+    ec_test! {
+        pre_validated_ascii,
+        {
+            use super::PreValidatedAscii;
+            PreValidatedAscii(SoftAsciiStr::from_unchecked("quoted-printable"))
+        } => Ascii => [
+            Text "quoted-printable"
+        ]
+    }
+
+    ec_test! {
+        ipv4_addr_as_domain_literal,
+        {
+            use std::net::{IpAddr, Ipv4Addr};
+            IpAddr::V4(Ipv4Addr::new(1, 2, 3, 4))
+        } => Ascii => [
+            Text "[1.2.3.4]"
+        ]
+    }
+
+    ec_test! {
+        ipv6_addr_as_domain_literal,
+        {
+            use std::net::{IpAddr, Ipv6Addr};
+            IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1))
+        } => Ascii => [
+            Text "[IPv6:2001:db8::1]"
+        ]
+    }
+
     mod trait_object {
         use super::super::*;
 
@@ -30,6 +30,15 @@ pub enum TraceToken {
     Body
 }
 
+use ::MailType;
+use ::error::EncodingError;
+use super::{EncodingBuffer, EncodableInHeader};
+
+/// A sink trace tokens can be passed to instead of accumulating them.
+///
+/// See `EncodingBuffer::with_trace_sink`.
+pub type TraceSink = Box<FnMut(&TraceToken) + Send>;
+
 pub fn simplify_trace_tokens<I: IntoIterator<Item=TraceToken>>(inp: I) -> Vec<TraceToken> {
     use std::mem;
     use self::TraceToken::*;
@@ -72,6 +81,21 @@ pub fn simplify_trace_tokens<I: IntoIterator<Item=TraceToken>>(inp: I) -> Vec<Tr
     out
 }
 
+/// Encodes the component as header returning the encoded header and the simplified trace.
+///
+/// This is like `ec_test!` but the header is finished (so the component
+/// has to write the whole header including its name) and errors are
+/// returned instead of panicking.
+pub fn encode_component_with_trace(component: &EncodableInHeader, mail_type: MailType)
+    -> Result<(String, Vec<TraceToken>), EncodingError>
+{
+    let mut encoder = EncodingBuffer::new(mail_type);
+    encoder.write_header_line(|handle| component.encode(handle))?;
+    let header = encoder.to_string()?;
+    let trace = simplify_trace_tokens(encoder.take_trace());
+    Ok((header, trace))
+}
+
 #[macro_export]
 macro_rules! ec_test {
     ( $(#[$attr:meta])* $name:ident, $inp:block => $mt:tt => [ $($tokens:tt)* ] ) => (
@@ -148,7 +172,9 @@ macro_rules! ec_test {
 #[cfg(test)]
 mod test {
     use soft_ascii_string::SoftAsciiStr;
+    use ::MailType;
     use super::super::encodable::EncodeClosure;
+    use super::*;
 
     ec_test!{ repreduces_all_tokens,
         {
@@ -164,4 +190,34 @@ mod test {
             Text " tshau-there"
         ]
     }
+
+    #[test]
+    fn encode_component_with_trace_returns_header_and_trace() {
+        use super::TraceToken::*;
+
+        let component = EncodeClosure::new(|writer| {
+            writer.write_str(SoftAsciiStr::from_unchecked("X-Test:"))?;
+            writer.write_fws();
+            writer.write_str(SoftAsciiStr::from_unchecked("value"))
+        });
+        let (header, trace) =
+            assert_ok!(encode_component_with_trace(&component, MailType::Ascii));
+
+        assert_eq!(header, "X-Test: value\r\n");
+        assert_eq!(trace, vec![
+            Text("X-Test:".to_owned()),
+            MarkFWS,
+            Text(" value".to_owned()),
+            CRLF,
+            End
+        ]);
+    }
+
+    #[test]
+    fn encode_component_with_trace_fails_like_the_component() {
+        let component = EncodeClosure::new(|writer| {
+            writer.write_utf8("X-Test: ü")
+        });
+        assert_err!(encode_component_with_trace(&component, MailType::Ascii));
+    }
 }
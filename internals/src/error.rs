@@ -31,11 +31,22 @@ pub enum EncodingErrorKind {
     #[fail(display = "malformed data")]
     Malformed,
 
+    #[fail(display = "line break starting a new header (header injection)")]
+    HeaderInjection,
+
     #[fail(display = "the mail body data cannot be accessed")]
     AccessingMailBodyFailed,
 
+    #[fail(display = "writing the encoded mail failed")]
+    WritingOutputFailed,
+
     #[fail(display = "{}", kind)]
-    Other { kind: &'static str }
+    Other { kind: &'static str },
+
+    /// New kinds can be added, so matches should not be exhaustive.
+    #[doc(hidden)]
+    #[fail(display = "__NonExhaustive")]
+    __NonExhaustive
 
     //ErrorKinds potentially needed when using this wrt. to decoding the mail encoding
     //UnsupportedEncoding { encoding: &'static str }
@@ -69,6 +80,18 @@ impl EncodingError {
         *self.inner.get_context()
     }
 
+    /// Returns a reference to the inner `Context` of the error.
+    pub fn context(&self) -> &Context<EncodingErrorKind> {
+        &self.inner
+    }
+
+    /// Turns the error into it's inner `Context`.
+    ///
+    /// Note that this drops the mail type, str context and place.
+    pub fn into_context(self) -> Context<EncodingErrorKind> {
+        self.inner
+    }
+
     /// Return the mail type used when the error appeared.
     pub fn mail_type(&self) -> Option<MailType> {
         self.mail_type
@@ -94,6 +117,32 @@ impl EncodingError {
         self
     }
 
+    /// Returns a concise message about the error which can be shown to a user.
+    ///
+    /// For errors caused by the encoded data (e.g. `Malformed`) this is
+    /// the kind followed by the str context (if there is one). For errors
+    /// not caused by the data (e.g. `AccessingMailBodyFailed`) it's only
+    /// the kind, as the str context might contain internal details.
+    /// The mail type is never included.
+    pub fn user_message(&self) -> String {
+        use self::EncodingErrorKind::*;
+        let kind = self.kind();
+        let is_caused_by_data = match kind {
+            InvalidTextEncoding { .. } |
+            HardLineLengthLimitBreached |
+            NotEncodable { .. } |
+            Malformed |
+            HeaderInjection => true,
+            AccessingMailBodyFailed |
+            WritingOutputFailed |
+            Other { .. } => false
+        };
+        match self.str_context() {
+            Some(ctx) if is_caused_by_data => format!("{}: {}", kind, ctx),
+            _ => kind.to_string()
+        }
+    }
+
     /// Adds a place (context) to self if there isn't one and returns self.
     pub fn with_place_or_else<F>(mut self, func: F) -> Self
         where F: FnOnce() -> Option<Place>
@@ -236,4 +285,31 @@ mod test {
         };
         assert!((func)().is_err());
     }
+
+    #[test]
+    fn kind_from_context_matches() {
+        use ::error::{EncodingError, EncodingErrorKind};
+
+        let err = EncodingError::from(EncodingErrorKind::Malformed);
+        assert_eq!(*err.context().get_context(), err.kind());
+        assert_eq!(*err.into_context().get_context(), EncodingErrorKind::Malformed);
+    }
+
+    #[test]
+    fn user_message_of_data_error_includes_context() {
+        use ::error::{EncodingError, EncodingErrorKind};
+
+        let err = EncodingError::from((EncodingErrorKind::Malformed, ::MailType::Ascii))
+            .with_str_context("a\rb");
+        assert_eq!(err.user_message(), "malformed data: a\rb");
+    }
+
+    #[test]
+    fn user_message_of_body_access_error_is_generic() {
+        use ::error::{EncodingError, EncodingErrorKind};
+
+        let err = EncodingError::from((EncodingErrorKind::AccessingMailBodyFailed, ::MailType::Ascii))
+            .with_str_context("/tmp/internal/path");
+        assert_eq!(err.user_message(), "the mail body data cannot be accessed");
+    }
 }
\ No newline at end of file
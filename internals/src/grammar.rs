@@ -3,6 +3,7 @@
 //! Ironically they are also needed when writing mail encoders/generators
 //! e.g. for checking if a part need special encoding.
 use ::MailType;
+use ::error::{EncodingError, EncodingErrorKind};
 
 /// ftext as defined by RFC 5322
 ///
@@ -190,37 +191,232 @@ pub fn is_token(s: &str) -> bool {
     0 < s.len() && s.chars().all(is_token_char)
 }
 
-//
-//pub fn is_dot_atom_text( text: &str, mt: MailType ) -> bool {
-//    use nom::IResult;
-//    use self::parse::recognize_dot_atom_text;
-//
-//    let res = tuple!( text,
-//        call!( recognize_dot_atom_text, mt ),
-//        eof!()
-//    );
-//
-//    match res {
-//        IResult::Done(_, _) => true,
-//        _ => false
-//    }
-//}
-
-//pub mod parse {
-//    use nom::IResult;
-//    use super::{ is_atext, MailType };
-//
-//    pub fn recognize_dot_atom_text( input: &str, mt: MailType ) -> IResult<&str, &str> {
-//        recognize!( input, tuple!(
-//            take_while1!( call!( is_atext, mt ) ),
-//            many0!( tuple!(
-//                char!( "." ),
-//                take_while1!( call!( is_atext, mt ) )
-//            ) )
-//        ) )
-//    }
-//
-//}
+/// Check if a string is an dot-atom-text (based on RFC 5322).
+///
+/// I.e. it's one or more atext chars, which can be separated
+/// by single dots (but can neither start nor end with a dot).
+pub fn is_dot_atom_text(text: &str, mt: MailType) -> bool {
+    validate_dot_atom(text, mt).is_ok()
+}
+
+/// The reason why a string is not a dot-atom-text.
+#[derive(Copy, Clone, Debug, Fail, PartialEq, Eq, Hash)]
+pub enum DotAtomError {
+    #[fail(display = "dot-atom is empty")]
+    Empty,
+
+    #[fail(display = "dot-atom starts with a dot")]
+    LeadingDot,
+
+    #[fail(display = "dot-atom ends with a dot")]
+    TrailingDot,
+
+    #[fail(display = "dot-atom contains two consecutive dots")]
+    DoubleDot,
+
+    /// The byte offset and the char which is neither atext nor a dot.
+    #[fail(display = "dot-atom contains invalid char {:?} at offset {}", _1, _0)]
+    InvalidChar(usize, char)
+}
+
+/// Validates that a string is an dot-atom-text (based on RFC 5322).
+///
+/// This is like `is_dot_atom_text` but returns the reason why
+/// the string is not a dot-atom-text if it isn't one.
+pub fn validate_dot_atom(text: &str, mt: MailType) -> Result<(), DotAtomError> {
+    if text.is_empty() {
+        return Err(DotAtomError::Empty);
+    }
+    let mut last_was_dot = false;
+    for (idx, ch) in text.char_indices() {
+        if ch == '.' {
+            if idx == 0 {
+                return Err(DotAtomError::LeadingDot);
+            }
+            if last_was_dot {
+                return Err(DotAtomError::DoubleDot);
+            }
+            last_was_dot = true;
+        } else if is_atext(ch, mt) {
+            last_was_dot = false;
+        } else {
+            return Err(DotAtomError::InvalidChar(idx, ch));
+        }
+    }
+    if last_was_dot {
+        Err(DotAtomError::TrailingDot)
+    } else {
+        Ok(())
+    }
+}
+
+/// Check if a string is an domain-literal (based on RFC 5322).
+///
+/// I.e. `"[" *dtext "]"`, note that (like on other places
+/// in this module) no FWS/comments are allowed.
+pub fn is_domain_literal(text: &str, mt: MailType) -> bool {
+    text.len() >= 2
+        && text.starts_with('[')
+        && text.ends_with(']')
+        && text[1..text.len()-1].chars().all(|ch| is_dtext(ch, mt))
+}
+
+/// Validates the content of a domain-literal, i.e. the part between `'['` and `']'`.
+///
+/// # Error
+///
+/// Fails with `Malformed` if any char of the content is not dtext,
+/// the content is used as str context.
+pub fn validate_domain_literal(content: &str, mt: MailType) -> Result<(), EncodingError> {
+    if content.chars().all(|ch| is_dtext(ch, mt)) {
+        Ok(())
+    } else {
+        Err(EncodingError::from((EncodingErrorKind::Malformed, mt))
+            .with_str_context(content))
+    }
+}
+
+/// Check if a string is an addr-spec (based on RFC 5322).
+///
+/// I.e. `local-part "@" domain` where the local part is either a
+/// dot-atom-text or a quoted string and the domain is either a
+/// dot-atom-text or a domain-literal. The string is split at the
+/// _last_ `'@'` as a quoted local part can contain `'@'`.
+///
+/// Like on other places in this module neither FWS/comments nor
+/// the obsolete syntax is supported.
+pub fn is_addr_spec(text: &str, mt: MailType) -> bool {
+    if let Some(idx) = text.rfind('@') {
+        let local = &text[..idx];
+        let domain = &text[idx+1..];
+        (is_dot_atom_text(local, mt) || is_quoted_string(local, mt))
+            && (is_dot_atom_text(domain, mt) || is_domain_literal(domain, mt))
+    } else {
+        false
+    }
+}
+
+/// Check if the text can be written as phrase without quoting it.
+///
+/// I.e. if it consists of atext (given the mail type) and WS and
+/// contains at least one atext char. Like on other places in this
+/// module encoded words and the obsolete syntax are not considered.
+pub fn is_unquoted_phrase(text: &str, mt: MailType) -> bool {
+    text.chars().any(|ch| !is_ws(ch))
+        && text.chars().all(|ch| is_ws(ch) || is_atext(ch, mt))
+}
+
+/// Unfolds a folded header (field body) like it is found "on the wire".
+///
+/// Each CRLF followed by WS is removed together with all WS directly
+/// following it and replaced by a single space, e.g. `"a\r\n b"` and
+/// `"a\r\n\t b"` both become `"a b"`.
+///
+/// # Error
+///
+/// Fails with `Malformed` if the value contains any `'\r'` or `'\n'`
+/// which is not part of a CRLF followed by WS.
+pub fn unfold_header(value: &str) -> Result<String, EncodingError> {
+    let mut out = String::with_capacity(value.len());
+    let mut chars = value.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\r' => {
+                let is_fold = chars.next() == Some('\n')
+                    && chars.peek().map_or(false, |ch| is_ws(*ch));
+
+                if !is_fold {
+                    return Err(EncodingError::from(EncodingErrorKind::Malformed)
+                        .with_str_context(value));
+                }
+
+                while chars.peek().map_or(false, |ch| is_ws(*ch)) {
+                    chars.next();
+                }
+                out.push(' ');
+            },
+            '\n' => {
+                return Err(EncodingError::from(EncodingErrorKind::Malformed)
+                    .with_str_context(value));
+            },
+            ch => out.push(ch)
+        }
+    }
+    Ok(out)
+}
+
+/// The result of scanning a text with `scan_text`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct TextScan {
+    /// true if the text contains any non us-ascii char
+    pub has_non_ascii: bool,
+    /// true if the text contains any `special` (see `is_special`)
+    pub has_specials: bool,
+    /// the byte offset of the first char which is neither vchar nor WS
+    /// (given the mail type)
+    pub first_non_vchar_offset: Option<usize>,
+    /// the byte offsets of all WS directly following a non WS char,
+    /// i.e. the places where a FWS can be marked before writing the WS
+    pub word_boundaries: Vec<usize>
+}
+
+/// Scans a text in one pass collecting everything needed to decide how to write it.
+///
+/// This allows components to choose between writing the text as
+/// atom, quoted string or encoded word and to place FWS marks without
+/// having to iterate over the text multiple times.
+pub fn scan_text(text: &str, mt: MailType) -> TextScan {
+    let mut scan = TextScan::default();
+    let mut last_was_ws = true;
+    for (idx, ch) in text.char_indices() {
+        scan.has_non_ascii |= !is_ascii(ch);
+        scan.has_specials |= is_special(ch);
+        if is_ws(ch) {
+            if !last_was_ws {
+                scan.word_boundaries.push(idx);
+            }
+            last_was_ws = true;
+        } else {
+            if scan.first_non_vchar_offset.is_none() && !is_vchar(ch, mt) {
+                scan.first_non_vchar_offset = Some(idx);
+            }
+            last_was_ws = false;
+        }
+    }
+    scan
+}
+
+/// Checks if the text contains a CRLF which is not followed by WS.
+///
+/// Such a text would start a new header (or with a blank line the body)
+/// if written as is, which is a header injection if it comes from untrusted
+/// input, e.g. `"x\r\nBcc: evil@x"` or `"x\r\n\r\nevil body"`. A CRLF followed
+/// by WS (i.e. a fold) is fine, as is a CRLF at the end of the text.
+///
+/// Note that this is stricter than only looking for a CRLF followed by
+/// a header name and a colon: any CRLF followed by something else than
+/// WS is rejected, as it would end the header either way (e.g. with
+/// `"x\r\nno colon"` the next line is a malformed header).
+pub fn contains_header_injection(s: &str) -> bool {
+    s.match_indices("\r\n").any(|(idx, _)| {
+        match s.as_bytes().get(idx+2) {
+            Some(&b' ') | Some(&b'\t') | None => false,
+            Some(_) => true
+        }
+    })
+}
+
+/// Strips invisible leading chars, i.e. any BOM (`'\u{FEFF}'`) and zero width spaces (`'\u{200B}'`).
+///
+/// User supplied text (e.g. a subject) sometimes starts with a BOM,
+/// which is valid but would show up as stray invisible char in the
+/// header. Other leading chars (including WS) are kept.
+pub fn strip_insignificant_prefix(s: &str) -> &str {
+    let start = s.find(|ch: char| ch != '\u{FEFF}' && ch != '\u{200B}')
+        .unwrap_or(s.len());
+    &s[start..]
+}
+
 //TODO this should be some where else I think
 // (but it is used by `1. codec`, `2. components` )
 /// Grammar parts for encoded words (based on RFC 2047).
@@ -419,5 +615,156 @@ mod test {
     fn is_toke_empty() {
         assert_eq!(false, is_token(""));
     }
-}
 
+    #[test]
+    fn dot_atom_text() {
+        assert!(is_dot_atom_text("a.b.c", MailType::Ascii));
+        assert_not!(is_dot_atom_text("", MailType::Ascii));
+        assert_not!(is_dot_atom_text(".a", MailType::Ascii));
+        assert_not!(is_dot_atom_text("a.", MailType::Ascii));
+        assert_not!(is_dot_atom_text("a..b", MailType::Ascii));
+        assert_not!(is_dot_atom_text("ä.b", MailType::Ascii));
+        assert!(is_dot_atom_text("ä.b", MailType::Internationalized));
+    }
+
+    #[test]
+    fn unquoted_phrase() {
+        assert!(is_unquoted_phrase("John Doe", MailType::Ascii));
+        assert!(is_unquoted_phrase("a", MailType::Ascii));
+        assert_not!(is_unquoted_phrase("", MailType::Ascii));
+        assert_not!(is_unquoted_phrase("  ", MailType::Ascii));
+        assert_not!(is_unquoted_phrase("John Q. Doe", MailType::Ascii));
+        assert_not!(is_unquoted_phrase("Jörg", MailType::Ascii));
+        assert!(is_unquoted_phrase("Jörg", MailType::Internationalized));
+    }
+
+    #[test]
+    fn addr_spec() {
+        assert!(is_addr_spec("a@b.c", MailType::Ascii));
+        assert_not!(is_addr_spec("a@@b", MailType::Ascii));
+        assert!(is_addr_spec("\"quoted\"@b", MailType::Ascii));
+        assert!(is_addr_spec("a@[127.0.0.1]", MailType::Ascii));
+        assert_not!(is_addr_spec("ab.c", MailType::Ascii));
+        assert_not!(is_addr_spec("ä@b.c", MailType::Ascii));
+        assert!(is_addr_spec("ä@b.c", MailType::Internationalized));
+    }
+
+    #[test]
+    fn detects_header_injection() {
+        assert!(contains_header_injection("x\r\nBcc: evil@x"));
+        assert!(contains_header_injection("x\r\n\r\nX-Other:y"));
+        assert!(contains_header_injection("x\r\n\r\nevil body"));
+        assert!(contains_header_injection("x\r\nno header name"));
+        assert!(contains_header_injection("x\r\n:y"));
+    }
+
+    #[test]
+    fn folds_are_not_header_injection() {
+        assert!(!contains_header_injection("x"));
+        assert!(!contains_header_injection("x\r\n y: z"));
+        assert!(!contains_header_injection("x\r\n"));
+        assert!(!contains_header_injection("x: y\r\n"));
+        assert!(!contains_header_injection("x\r\n\tz"));
+    }
+
+    #[test]
+    fn strip_bom_prefix() {
+        assert_eq!(strip_insignificant_prefix("\u{FEFF}Hy there"), "Hy there");
+        assert_eq!(strip_insignificant_prefix("\u{200B}\u{FEFF}\u{200B}Hy"), "Hy");
+        assert_eq!(strip_insignificant_prefix("\u{FEFF}"), "");
+    }
+
+    #[test]
+    fn strip_insignificant_prefix_keeps_normal_text() {
+        assert_eq!(strip_insignificant_prefix("Hy there"), "Hy there");
+        assert_eq!(strip_insignificant_prefix(" Hy\u{FEFF}"), " Hy\u{FEFF}");
+        assert_eq!(strip_insignificant_prefix(""), "");
+    }
+
+    #[test]
+    fn unfold_folded_header() {
+        let unfolded = assert_ok!(unfold_header("a\r\n b\r\n   c"));
+        assert_eq!(unfolded, "a b c");
+    }
+
+    #[test]
+    fn unfold_tab_folded_header() {
+        let unfolded = assert_ok!(unfold_header("a\r\n\tb"));
+        assert_eq!(unfolded, "a b");
+    }
+
+    #[test]
+    fn unfold_malformed_header() {
+        assert_err!(unfold_header("a\nb"));
+        assert_err!(unfold_header("a\rb"));
+        assert_err!(unfold_header("a\r\nb"));
+        assert_err!(unfold_header("a\r\n"));
+    }
+
+    #[test]
+    fn scan_text_with_space() {
+        assert_eq!(scan_text("a b", MailType::Ascii), TextScan {
+            has_non_ascii: false,
+            has_specials: false,
+            first_non_vchar_offset: None,
+            word_boundaries: vec![1]
+        });
+    }
+
+    #[test]
+    fn scan_text_with_non_ascii() {
+        assert_eq!(scan_text("Grüße", MailType::Ascii), TextScan {
+            has_non_ascii: true,
+            has_specials: false,
+            first_non_vchar_offset: Some(2),
+            word_boundaries: vec![]
+        });
+        assert_eq!(
+            scan_text("Grüße", MailType::Internationalized).first_non_vchar_offset,
+            None
+        );
+    }
+
+    #[test]
+    fn scan_text_with_tab() {
+        assert_eq!(scan_text("a\tb", MailType::Ascii), TextScan {
+            has_non_ascii: false,
+            has_specials: false,
+            first_non_vchar_offset: None,
+            word_boundaries: vec![1]
+        });
+    }
+
+    #[test]
+    fn validate_dot_atom_errors() {
+        assert_ok!(validate_dot_atom("a.b", MailType::Ascii));
+        assert_eq!(validate_dot_atom("", MailType::Ascii), Err(DotAtomError::Empty));
+        assert_eq!(validate_dot_atom(".a", MailType::Ascii), Err(DotAtomError::LeadingDot));
+        assert_eq!(validate_dot_atom("a.", MailType::Ascii), Err(DotAtomError::TrailingDot));
+        assert_eq!(validate_dot_atom("a..b", MailType::Ascii), Err(DotAtomError::DoubleDot));
+        assert_eq!(
+            validate_dot_atom("a.b@c", MailType::Ascii),
+            Err(DotAtomError::InvalidChar(3, '@'))
+        );
+    }
+
+    #[test]
+    fn dtext_boundaries() {
+        for ch in &['!', 'Z', '^', '~'] {
+            assert!(is_dtext(*ch, MailType::Ascii), "{:?} should be dtext", ch);
+        }
+        for ch in &[' ', '[', '\\', ']', '\x7f', 'ä'] {
+            assert_not!(is_dtext(*ch, MailType::Ascii), "{:?} should not be dtext", ch);
+        }
+        assert!(is_dtext('ä', MailType::Internationalized));
+    }
+
+    #[test]
+    fn validate_domain_literal_content() {
+        assert_ok!(validate_domain_literal("IPv6:2001:db8::1", MailType::Ascii));
+        assert_ok!(validate_domain_literal("192.0.2.1", MailType::Ascii));
+        let err = assert_err!(validate_domain_literal("a]b", MailType::Ascii));
+        assert_eq!(err.kind(), EncodingErrorKind::Malformed);
+        assert_err!(validate_domain_literal("a\\b", MailType::Ascii));
+    }
+}
\ No newline at end of file
@@ -120,10 +120,12 @@ pub fn vec_insert_bytes(target: &mut Vec<u8>, idx: usize, source: &[u8]) {
     let old_len = target.len();
     let insertion_len = source.len();
     let source_ptr = source.as_ptr();
-    let insertion_point = unsafe { target.as_mut_ptr().offset(idx as isize) };
+    assert!(idx <= old_len, "insertion index out of bounds");
     let moved_data_len = old_len - idx;
 
+    // reserve first, as it can reallocate which would invalidate any pointer into target
     target.reserve(insertion_len);
+    let insertion_point = unsafe { target.as_mut_ptr().offset(idx as isize) };
 
     unsafe {
         copy(/*src*/insertion_point,
@@ -135,4 +137,60 @@ pub fn vec_insert_bytes(target: &mut Vec<u8>, idx: usize, source: &[u8]) {
         //3. set the new len for the vec
         target.set_len(old_len + insertion_len)
     }
-}
\ No newline at end of file
+}
+
+/// Groups values so that each group joined with `", "` is at most `max_per_header` bytes long.
+///
+/// This is meant for headers which can appear multiple times instead of
+/// being folded (e.g. `Keywords`), each group can then be written as it's
+/// own header. Values which on their own exceed the budget are put into
+/// a group containing only them.
+pub fn split_into_multiple<'a>(values: &[&'a str], max_per_header: usize) -> Vec<Vec<&'a str>> {
+    const SEPARATOR_LEN: usize = 2;
+    let mut groups = Vec::new();
+    let mut current = Vec::new();
+    let mut current_len = 0;
+    for &value in values {
+        if !current.is_empty() {
+            if current_len + SEPARATOR_LEN + value.len() > max_per_header {
+                groups.push(mem::replace(&mut current, Vec::new()));
+                current_len = 0;
+            } else {
+                current_len += SEPARATOR_LEN;
+            }
+        }
+        current_len += value.len();
+        current.push(value);
+    }
+    if !current.is_empty() {
+        groups.push(current);
+    }
+    groups
+}
+
+#[cfg(test)]
+mod test {
+    use super::split_into_multiple;
+
+    #[test]
+    fn split_keywords_into_multiple_headers() {
+        let keywords = &[
+            "one", "two", "three", "four", "five", "six", "seven", "eight"
+        ];
+        assert_eq!(split_into_multiple(keywords, 15), vec![
+            vec!["one", "two", "three"],
+            vec!["four", "five", "six"],
+            vec!["seven", "eight"]
+        ]);
+    }
+
+    #[test]
+    fn split_with_oversized_value() {
+        let values = &["a", "way-too-long", "b"];
+        assert_eq!(split_into_multiple(values, 4), vec![
+            vec!["a"],
+            vec!["way-too-long"],
+            vec!["b"]
+        ]);
+    }
+}